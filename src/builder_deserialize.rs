@@ -1,151 +1,260 @@
+use crate::closure::{
+    into_operands, operands, read_cell, repeat_operands, resolve_pure, take_cell, SIZE_HINT_LIMIT,
+};
+use crate::list_access::RoundRobin;
 use crate::{
-    BuilderDataType, BuilderDeserializerRef, BuilderError, BuilderListAccess, BuilderListAccessRef,
-    BuilderMapAccess, Closure,
+    BuilderDataType, BuilderDeserializerRef, BuilderEnumAccess, BuilderError, BuilderListAccess,
+    BuilderListAccessRef, BuilderMapAccess, BuilderRepeatMapAccess, BuilderUnfoldAccess,
+    BuilderWhileAccess, Closure, DuplicateKeyPolicy,
 };
-use serde::de::Visitor;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use serde::de::{Error, Visitor};
 use serde::forward_to_deserialize_any;
-use std::borrow::Cow;
-use std::rc::Rc;
+use serde::Deserializer;
 
 pub struct BuilderDeserializer<'s, 'de> {
     pub(crate) closure: &'s mut Closure<'de>,
     pub(crate) data: BuilderDataType<'de>,
 }
 
-impl<'s, 'de> serde::Deserializer<'de> for BuilderDeserializer<'s, 'de> {
-    type Error = BuilderError;
+/// Visits a `Number` with no fractional part as the integer it holds, so it deserializes into
+/// integer types. Other numbers are visited as `f64`.
+pub(crate) fn visit_integral<'de, V>(v: f64, visitor: V) -> Result<V::Value, BuilderError>
+where
+    V: Visitor<'de>,
+{
+    if v >= i64::MIN as f64 && v < i64::MAX as f64 && v as i64 as f64 == v {
+        visitor.visit_i64(v as i64)
+    } else if v >= 0.0 && v < u64::MAX as f64 && v as u64 as f64 == v {
+        visitor.visit_u64(v as u64)
+    } else {
+        visitor.visit_f64(v)
+    }
+}
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+/// Narrows a `Number` for an `f32` field, failing if it is finite but too large for `f32`.
+pub(crate) fn narrow_f32(v: f64) -> Result<f32, BuilderError> {
+    let narrowed = v as f32;
+    if narrowed.is_infinite() && v.is_finite() {
+        Err(BuilderError::FloatOverflow(v))
+    } else {
+        Ok(narrowed)
+    }
+}
+
+/// Visits a string holding a number as that number, for integer fields outside strict mode.
+pub(crate) fn visit_numeric_str<'de, V>(s: &str, visitor: V) -> Result<V::Value, BuilderError>
+where
+    V: Visitor<'de>,
+{
+    let s = s.trim();
+    if let Ok(v) = s.parse::<i64>() {
+        visitor.visit_i64(v)
+    } else if let Ok(v) = s.parse::<u64>() {
+        visitor.visit_u64(v)
+    } else if let Ok(v) = s.parse::<f64>() {
+        visit_integral(v, visitor)
+    } else {
+        Err(BuilderError::ParseFailed(String::from(s)))
+    }
+}
+
+/// Formats a boolean or number for a string field; other values have no string form.
+pub(crate) fn stringify_scalar(data: &BuilderDataType) -> Option<String> {
+    match data {
+        BuilderDataType::Boolean(v) => Some(v.to_string()),
+        BuilderDataType::Integer(v) => Some(v.to_string()),
+        BuilderDataType::Unsigned(v) => Some(v.to_string()),
+        BuilderDataType::Number(v) => Some(v.to_string()),
+        BuilderDataType::Float32(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+/// Overrides integer deserialization with the given helper, `deserialize_integer` or
+/// `deserialize_unsigned`.
+macro_rules! deserialize_integers {
+    ($helper:ident: $($method:ident)*) => {$(
+        fn $method<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+        where
+            V: Visitor<'de>,
+        {
+            self.$helper(visitor)
+        }
+    )*};
+}
+pub(crate) use deserialize_integers;
+
+impl<'s, 'de> BuilderDeserializer<'s, 'de> {
+    fn deserialize_integer<V>(self, visitor: V) -> Result<V::Value, BuilderError>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_i64(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Number(v) => visit_integral(v, visitor),
+            BuilderDataType::Float32(v) => visit_integral(f64::from(v), visitor),
+            BuilderDataType::String(s) if !self.closure.strict => visit_numeric_str(&s, visitor),
+            BuilderDataType::Boolean(v) if !self.closure.strict => visitor.visit_u64(u64::from(v)),
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    /// Like `deserialize_integer`, but rejects a negative `Integer` instead of passing it on.
+    fn deserialize_unsigned<V>(self, visitor: V) -> Result<V::Value, BuilderError>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_u64(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Integer(value) if value < 0 => {
+                Err(BuilderError::NegativeToUnsigned { value })
+            }
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_integer(visitor),
+        }
+    }
+
+    fn deserialize_node<V>(self, visitor: V) -> Result<V::Value, BuilderError>
     where
         V: Visitor<'de>,
     {
         match self.data {
-            BuilderDataType::Empty => todo!(),
+            BuilderDataType::Empty | BuilderDataType::Null => visitor.visit_unit(),
             BuilderDataType::Boolean(v) => visitor.visit_bool(v),
             BuilderDataType::Integer(v) => visitor.visit_i64(v),
             BuilderDataType::Unsigned(v) => visitor.visit_u64(v),
             BuilderDataType::Number(v) => visitor.visit_f64(v),
+            BuilderDataType::Float32(v) => visitor.visit_f32(v),
             BuilderDataType::String(c) => match c {
                 Cow::Borrowed(v) => visitor.visit_borrowed_str(v),
                 Cow::Owned(v) => visitor.visit_string(v),
             },
-            BuilderDataType::Map(v) => {
-                let size_hint = Some(v.len());
+            BuilderDataType::Bytes(c) => match c {
+                Cow::Borrowed(v) => visitor.visit_borrowed_bytes(v),
+                Cow::Owned(v) => visitor.visit_byte_buf(v),
+            },
+            BuilderDataType::Map(mut v) => {
+                if self.closure.duplicate_keys == DuplicateKeyPolicy::Merge {
+                    v = self.closure.merge_entries(v)?;
+                }
+                if self.closure.group_keys {
+                    v = self.closure.group_entries(v)?;
+                }
+                if self.closure.sort_maps {
+                    v.sort_by_cached_key(|(k, _)| k.to_string());
+                }
+                let size_hint = self.closure.map_len(&v);
                 visitor.visit_map(BuilderMapAccess {
                     closure: self.closure,
                     data: v.into_iter(),
                     leftover: None,
                     size_hint,
+                    seen: BTreeSet::new(),
+                    pending: Vec::new(),
                 })
             }
             BuilderDataType::List(v) => {
-                let size_hint = Some(v.len());
+                let size_hint = self.closure.list_len(&v);
                 visitor.visit_seq(BuilderListAccess {
                     closure: self.closure,
                     data: v.into_iter(),
                     size_hint,
                     index: 0,
+                    pending: Vec::new(),
                 })
             }
             BuilderDataType::Closure(v) => {
-                if let Some(r) = v.first().cloned() {
-                    let mut closure = Closure {
-                        args: v
-                            .into_iter()
-                            .map(|a| self.closure.resolve(a))
-                            .collect::<Result<Vec<_>, _>>()?,
-                        index: self.closure.index,
-                    };
-                    BuilderDeserializer {
-                        closure: &mut closure,
-                        data: r,
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidFunctionArgument)
+                let mut v = v.into_iter();
+                let Some(r) = v.next() else {
+                    return Err(BuilderError::EmptyClosure);
+                };
+                self.closure.check_args(&r, v.len() + 1)?;
+                // The body is its own `Argument(0)` unevaluated, so it only runs as the body.
+                let args = core::iter::once(Ok(r.clone()))
+                    .chain(v.map(|a| self.closure.resolve(a)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut closure = self.closure.nested(args);
+                BuilderDeserializer {
+                    closure: &mut closure,
+                    data: r,
                 }
+                .deserialize_any(visitor)
             }
-            BuilderDataType::Argument(a) => {
-                if let Some(p) = self.closure.args.get(a).cloned() {
-                    BuilderDeserializer {
-                        closure: self.closure,
-                        data: p,
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidFunctionArgument)
+            BuilderDataType::Apply(body, v) => {
+                self.closure.check_args(&body, v.len())?;
+                let args = v
+                    .into_iter()
+                    .map(|a| self.closure.resolve(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut closure = self.closure.nested(args);
+                BuilderDeserializer {
+                    closure: &mut closure,
+                    data: *body,
                 }
+                .deserialize_any(visitor)
             }
-            BuilderDataType::TakeFromArgument(a) => {
-                if let Some(p) = self.closure.args.get_mut(a).map(|r| r.take_one()) {
-                    BuilderDeserializer {
-                        closure: self.closure,
-                        data: p,
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidFunctionArgument)
+            BuilderDataType::Argument(a) => {
+                let argument = self.closure.shared_argument(a)?;
+                deserialize_indirect(self.closure, Indirect::Shared(argument), visitor)
+            }
+            BuilderDataType::ArgumentOr(a, d) => match self.closure.shared_argument(a) {
+                Ok(argument) => {
+                    deserialize_indirect(self.closure, Indirect::Shared(argument), visitor)
                 }
+                Err(_) => BuilderDeserializer {
+                    closure: self.closure,
+                    data: *d,
+                }
+                .deserialize_any(visitor),
+            },
+            BuilderDataType::TakeFromArgument(a) => BuilderDeserializer {
+                data: self.closure.take_from_argument(a)?,
+                closure: self.closure,
             }
+            .deserialize_any(visitor),
             BuilderDataType::PopArgument => {
                 if let Some(p) = self.closure.args.pop() {
                     BuilderDeserializer {
                         closure: self.closure,
-                        data: p,
+                        data: Rc::unwrap_or_clone(p),
                     }
                     .deserialize_any(visitor)
                 } else {
                     Err(BuilderError::InvalidFunctionArgument)
                 }
             }
-            BuilderDataType::Reference(r) => {
-                if Rc::weak_count(&r) > 0 {
-                    BuilderDeserializerRef {
-                        closure: self.closure,
-                        data: &r,
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    match Rc::try_unwrap(r) {
-                        Ok(data) => BuilderDeserializer {
-                            closure: self.closure,
-                            data,
-                        }
-                        .deserialize_any(visitor),
-                        Err(r) => BuilderDeserializerRef {
-                            closure: self.closure,
-                            data: &r,
-                        }
-                        .deserialize_any(visitor),
-                    }
-                }
+            data @ (BuilderDataType::Reference(_)
+            | BuilderDataType::SelfReference(_)
+            | BuilderDataType::Store(_)) => {
+                deserialize_indirect(self.closure, Indirect::Owned(data), visitor)
             }
-            BuilderDataType::SelfReference(w) => {
-                if let Some(r) = w.upgrade() {
-                    BuilderDeserializerRef {
-                        closure: self.closure,
-                        data: r.as_ref(),
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidSelfRefrence)
-                }
-            }
-            BuilderDataType::Store(r) => match Rc::try_unwrap(r) {
-                Ok(c) => BuilderDeserializer {
-                    closure: self.closure,
-                    data: c.into_inner(),
-                }
-                .deserialize_any(visitor),
-                Err(r) => BuilderDeserializer {
+            BuilderDataType::SelfReferenceOr(w, d) => match w.upgrade() {
+                Some(r) => deserialize_indirect(self.closure, Indirect::Shared(r), visitor),
+                None => BuilderDeserializer {
                     closure: self.closure,
-                    data: r.as_ref().borrow().clone(),
+                    data: *d,
                 }
                 .deserialize_any(visitor),
             },
             BuilderDataType::Take(r) => BuilderDeserializer {
                 closure: self.closure,
-                data: r.as_ref().borrow_mut().take_one(),
+                data: take_cell(&r)?,
             }
             .deserialize_any(visitor),
             BuilderDataType::IfThenElse(v) => BuilderDeserializer {
@@ -153,23 +262,651 @@ impl<'s, 'de> serde::Deserializer<'de> for BuilderDeserializer<'s, 'de> {
                 closure: self.closure,
             }
             .deserialize_any(visitor),
+            BuilderDataType::Switch(v) => BuilderDeserializer {
+                data: self.closure.switch(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Weighted(v) => BuilderDeserializer {
+                data: self.closure.weighted(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Assert(v) => BuilderDeserializer {
+                data: self.closure.assert(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
             BuilderDataType::Repeat(v) => {
-                let mut it = v.iter();
-                let times = it.next().map_or(0, |r| r.to_unsigned());
+                let (times, body) = repeat_operands(&v)?;
+                let times = self.closure.evaluate(times.clone())?.to_unsigned();
+                let body = resolve_pure(self.closure, body, times as usize)?;
                 visitor.visit_seq(BuilderListAccessRef {
                     closure: self.closure,
-                    data: it.cycle().take(times as usize),
+                    data: body.iter().cycle().take(times as usize),
+                    index: 0,
+                    size_hint: Some((times as usize).min(SIZE_HINT_LIMIT)),
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::RepeatMap(v) => {
+                let [count, key, value] = operands("RepeatMap", &v)?;
+                visitor.visit_map(BuilderRepeatMapAccess {
+                    count: self.closure.evaluate(count.clone())?.to_unsigned() as usize,
+                    closure: self.closure,
+                    key,
+                    value,
+                    index: 0,
+                })
+            }
+            BuilderDataType::Interleave(v) => {
+                let lists = v
+                    .into_iter()
+                    .map(|l| self.closure.resolve_list(l).map(Vec::into_iter))
+                    .collect::<Result<Vec<_>, _>>()?;
+                visitor.visit_seq(BuilderListAccess::new(self.closure, RoundRobin::new(lists)))
+            }
+            BuilderDataType::Unfold(v) => {
+                let [seed, count, step] = operands("Unfold", &v)?;
+                visitor.visit_seq(BuilderUnfoldAccess {
+                    current: self.closure.evaluate(seed.clone())?,
+                    count: self.closure.evaluate(count.clone())?.to_unsigned() as usize,
+                    closure: self.closure,
+                    step,
+                    index: 0,
+                })
+            }
+            BuilderDataType::While(v) => {
+                let [condition, body] = operands("While", &v)?;
+                visitor.visit_seq(BuilderWhileAccess {
+                    closure: self.closure,
+                    condition,
+                    body,
+                    index: 0,
+                })
+            }
+            BuilderDataType::Sum(v) => BuilderDeserializer {
+                data: self.closure.sum(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Multiply(v) => BuilderDeserializer {
+                data: self.closure.multiply(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Divide(v) => BuilderDeserializer {
+                data: self.closure.divide(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::ParseInt(v) => BuilderDeserializer {
+                data: self.closure.parse_int(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::ParseFloat(v) => BuilderDeserializer {
+                data: self.closure.parse_float(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Path(v) => BuilderDeserializer {
+                data: self.closure.path(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Tap(v) => BuilderDeserializer {
+                data: self.closure.tap(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Quote(v) => BuilderDeserializer {
+                data: self.closure.quote(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Shuffle(n) => BuilderDeserializer {
+                data: self.closure.shuffle(*n)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Replace(v) => BuilderDeserializer {
+                data: self.closure.replace(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Variant(v) => {
+                let [tag, payload] = into_operands("Variant", v)?;
+                BuilderDeserializer {
+                    closure: self.closure,
+                    data: BuilderDataType::Map(vec![(tag, payload)]),
+                }
+                .deserialize_any(visitor)
+            }
+            BuilderDataType::Try(v) => BuilderDeserializer {
+                data: self.closure.attempt(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Range(v) => {
+                let v = self.closure.range(v)?;
+                let size_hint = Some(v.len());
+                visitor.visit_seq(BuilderListAccess {
+                    closure: self.closure,
+                    data: v.into_iter(),
+                    size_hint,
                     index: 0,
-                    size_hint: Some(times as usize),
+                    pending: Vec::new(),
                 })
             }
+            BuilderDataType::Scan(v) => {
+                let v = self.closure.scan(v)?;
+                let size_hint = Some(v.len());
+                visitor.visit_seq(BuilderListAccess {
+                    closure: self.closure,
+                    data: v.into_iter(),
+                    size_hint,
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Partition(v) => BuilderDeserializer {
+                data: self.closure.partition(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
             BuilderDataType::Index => visitor.visit_u64(self.closure.index as u64),
-            _ => todo!(),
+            BuilderDataType::Toggle(t) => visitor.visit_bool(t.replace_with(|b| !*b)),
+            BuilderDataType::ArgCount => visitor.visit_u64(self.closure.args.len() as u64),
+            BuilderDataType::Pad(v) => {
+                let v = self.closure.pad(v)?;
+                let size_hint = Some(v.len());
+                visitor.visit_seq(BuilderListAccess {
+                    closure: self.closure,
+                    data: v.into_iter(),
+                    size_hint,
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Let(v) => {
+                let body = self.closure.bind(v)?;
+                let result = BuilderDeserializer {
+                    closure: &mut *self.closure,
+                    data: body,
+                }
+                .deserialize_any(visitor);
+                self.closure.bindings.pop();
+                result
+            }
+            BuilderDataType::DefineFn(v) => {
+                let body = self.closure.define(v)?;
+                let result = BuilderDeserializer {
+                    closure: &mut *self.closure,
+                    data: body,
+                }
+                .deserialize_any(visitor);
+                self.closure.functions.pop();
+                result
+            }
+            BuilderDataType::CallFn(v) => {
+                let (function, args) = self.closure.call(v)?;
+                let mut closure = self.closure.nested(args);
+                BuilderDeserializerRef {
+                    closure: &mut closure,
+                    data: &function,
+                }
+                .deserialize_any(visitor)
+            }
+            BuilderDataType::Var(n) => BuilderDeserializer {
+                data: self.closure.var(&n)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Lazy(n) | BuilderDataType::Comment(n, _) => BuilderDeserializer {
+                closure: self.closure,
+                data: *n,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "std-time")]
+            BuilderDataType::Now => visitor.visit_u64((self.closure.clock)()),
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(n) => BuilderDeserializer {
+                data: self.closure.env(&n)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Duration(v) => BuilderDeserializer {
+                data: self.closure.duration(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) => BuilderDeserializer {
+                data: self.closure.matches(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Capture(v) => BuilderDeserializer {
+                data: self.closure.capture_group(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => BuilderDeserializer {
+                data: self.closure.format_date(v)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            b => Err(BuilderError::UnsupportedVariant(b.kind())),
+        }
+    }
+}
+
+impl<'s, 'de> serde::Deserializer<'de> for BuilderDeserializer<'s, 'de> {
+    type Error = BuilderError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let BuilderDeserializer { closure, data } = self;
+        if closure.depth >= closure.recursion_limit {
+            return Err(BuilderError::RecursionLimit(closure.recursion_limit));
+        }
+        let stats = closure.stats.clone();
+        if let Some(stats) = &stats {
+            stats.borrow_mut().enter(data.kind());
+        }
+        closure.depth += 1;
+        let result = BuilderDeserializer {
+            closure: &mut *closure,
+            data,
+        }
+        .deserialize_node(visitor);
+        closure.depth -= 1;
+        if let Some(stats) = &stats {
+            stats.borrow_mut().leave();
+        }
+        result
+    }
+
+    deserialize_integers! {
+        deserialize_integer:
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+    }
+
+    deserialize_integers! {
+        deserialize_unsigned:
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_f32(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Float32(v) => visitor.visit_f32(v),
+            BuilderDataType::Number(v) => visitor.visit_f32(narrow_f32(v)?),
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_option(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Empty => visitor.visit_none(),
+            BuilderDataType::Null => visitor.visit_unit(),
+            BuilderDataType::SelfReferenceOr(w, d) if w.strong_count() == 0 => {
+                BuilderDeserializer {
+                    closure: self.closure,
+                    data: *d,
+                }
+                .deserialize_option(visitor)
+            }
+            BuilderDataType::Take(r) => BuilderDeserializer {
+                closure: self.closure,
+                data: take_cell(&r)?,
+            }
+            .deserialize_option(visitor),
+            data => visitor.visit_some(BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_bool(visitor));
+        }
+        if self.closure.strict {
+            return self.deserialize_any(visitor);
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Boolean(v) => visitor.visit_bool(v),
+            BuilderDataType::String(s) if s.eq_ignore_ascii_case("true") => {
+                visitor.visit_bool(true)
+            }
+            BuilderDataType::String(s) if s.eq_ignore_ascii_case("false") => {
+                visitor.visit_bool(false)
+            }
+            data @ (BuilderDataType::Empty
+            | BuilderDataType::Integer(_)
+            | BuilderDataType::Unsigned(_)
+            | BuilderDataType::Number(_)
+            | BuilderDataType::Float32(_)
+            | BuilderDataType::String(_)) => visitor.visit_bool(data.check_true()),
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_str(visitor));
+        }
+        if self.closure.strict {
+            return self.deserialize_any(visitor);
+        }
+        let data = self.closure.resolve(self.data)?;
+        match stringify_scalar(&data) {
+            Some(s) => visitor.visit_string(s),
+            None => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_seq(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Empty => {
+                visitor.visit_seq(BuilderListAccess::new(self.closure, core::iter::empty()))
+            }
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_map(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Empty => {
+                visitor.visit_map(BuilderMapAccess::new(self.closure, core::iter::empty()))
+            }
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_tuple(len, visitor));
+        }
+        let data = self.closure.resolve(self.data)?;
+        if let BuilderDataType::List(v) = &data {
+            if let Some(found) = self.closure.list_len(v).filter(|&n| n != len) {
+                return Err(BuilderError::ArrayLengthMismatch {
+                    expected: len,
+                    found,
+                });
+            }
+        }
+        BuilderDeserializer {
+            closure: self.closure,
+            data,
+        }
+        .deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| {
+                d.deserialize_tuple_struct(name, len, visitor)
+            });
+        }
+        let data = self.closure.resolve(self.data)?;
+        if let BuilderDataType::List(v) = &data {
+            if let Some(found) = self.closure.list_len(v).filter(|&n| n != len) {
+                return Err(BuilderError::invalid_length(
+                    found,
+                    &format!("tuple struct {name} of length {len}").as_str(),
+                ));
+            }
+        }
+        BuilderDeserializer {
+            closure: self.closure,
+            data,
+        }
+        .deserialize_any(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| {
+                d.deserialize_struct(name, fields, visitor)
+            });
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Map(v) => match self.closure.field_order(&v, fields)? {
+                Some(order) => {
+                    let mut slots: Vec<_> = v.into_iter().map(Some).collect();
+                    let entries: Vec<_> =
+                        order.into_iter().filter_map(|i| slots[i].take()).collect();
+                    visitor.visit_map(BuilderMapAccess::new(self.closure, entries.into_iter()))
+                }
+                None => BuilderDeserializer {
+                    closure: self.closure,
+                    data: BuilderDataType::Map(v),
+                }
+                .deserialize_any(visitor),
+            },
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.closure.evaluate(self.data)? {
+            BuilderDataType::Map(mut v) if v.len() == 1 => {
+                let Some((variant, value)) = v.pop() else {
+                    return Err(BuilderError::InvalidMapAccess);
+                };
+                visitor.visit_enum(BuilderEnumAccess {
+                    closure: self.closure,
+                    variant,
+                    value: Some(value),
+                })
+            }
+            BuilderDataType::Variant(v) => {
+                let [variant, value] = into_operands("Variant", v)?;
+                visitor.visit_enum(BuilderEnumAccess {
+                    closure: self.closure,
+                    variant,
+                    value: Some(value),
+                })
+            }
+            variant @ (BuilderDataType::String(_)
+            | BuilderDataType::Unsigned(_)
+            | BuilderDataType::Integer(_)) => visitor.visit_enum(BuilderEnumAccess {
+                closure: self.closure,
+                variant,
+                value: None,
+            }),
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if let BuilderDataType::Argument(a) = self.data {
+            return with_argument(self.closure, a, |d| d.deserialize_identifier(visitor));
+        }
+        match self.closure.resolve(self.data)? {
+            BuilderDataType::Integer(v) if v >= 0 => visitor.visit_u64(v as u64),
+            data => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_any(visitor),
         }
     }
     forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option unit unit_struct newtype_struct seq tuple
-        tuple_struct map struct enum identifier ignored_any
+        f64 char bytes byte_buf
+        unit unit_struct newtype_struct ignored_any
+    }
+}
+
+/// A node reached while following `Reference`, `SelfReference` and `Store` layers, either owned
+/// outright or shared with the rest of the graph.
+pub(crate) enum Indirect<'de> {
+    Owned(BuilderDataType<'de>),
+    Shared(Rc<BuilderDataType<'de>>),
+}
+
+/// Follows consecutive indirection layers in a loop, stopping at the first other node. Owned
+/// references are unwrapped when nothing else holds them and no `SelfReference` points into them.
+fn follow(mut node: Indirect<'_>) -> Result<Indirect<'_>, BuilderError> {
+    loop {
+        node = match node {
+            Indirect::Owned(BuilderDataType::Reference(r)) if Rc::weak_count(&r) > 0 => {
+                Indirect::Shared(r)
+            }
+            Indirect::Owned(BuilderDataType::Reference(r)) => match Rc::try_unwrap(r) {
+                Ok(data) => Indirect::Owned(data),
+                Err(r) => Indirect::Shared(r),
+            },
+            Indirect::Owned(BuilderDataType::SelfReference(w)) => {
+                Indirect::Shared(w.upgrade().ok_or(BuilderError::InvalidSelfRefrence)?)
+            }
+            Indirect::Owned(BuilderDataType::Store(r)) => match Rc::try_unwrap(r) {
+                Ok(c) => Indirect::Owned(c.into_inner()),
+                Err(r) => Indirect::Owned(read_cell(&r)?.clone()),
+            },
+            Indirect::Shared(r) => match r.as_ref() {
+                BuilderDataType::Reference(inner) => Indirect::Shared(inner.clone()),
+                BuilderDataType::SelfReference(w) => {
+                    Indirect::Shared(w.upgrade().ok_or(BuilderError::InvalidSelfRefrence)?)
+                }
+                BuilderDataType::Store(c) => Indirect::Owned(read_cell(c)?.clone()),
+                _ => return Ok(Indirect::Shared(r)),
+            },
+            node => return Ok(node),
+        }
+    }
+}
+
+/// Runs `f` on a borrowed deserializer over argument `a`, sharing it rather than cloning.
+pub(crate) fn with_argument<'de, T>(
+    closure: &mut Closure<'de>,
+    a: usize,
+    f: impl FnOnce(BuilderDeserializerRef<'_, '_, 'de>) -> Result<T, BuilderError>,
+) -> Result<T, BuilderError> {
+    let argument = closure.shared_argument(a)?;
+    f(BuilderDeserializerRef {
+        closure,
+        data: &argument,
+    })
+}
+
+pub(crate) fn deserialize_indirect<'de, V>(
+    closure: &mut Closure<'de>,
+    node: Indirect<'de>,
+    visitor: V,
+) -> Result<V::Value, BuilderError>
+where
+    V: Visitor<'de>,
+{
+    match follow(node)? {
+        Indirect::Owned(data) => BuilderDeserializer { closure, data }.deserialize_any(visitor),
+        Indirect::Shared(r) => BuilderDeserializerRef {
+            closure,
+            data: r.as_ref(),
+        }
+        .deserialize_any(visitor),
     }
 }
@@ -1,127 +1,802 @@
+use crate::builder_deserialize::{
+    deserialize_indirect, deserialize_integers, narrow_f32, stringify_scalar, visit_integral,
+    visit_numeric_str, with_argument, Indirect,
+};
+use crate::closure::{
+    operands, read_cell, repeat_operands, resolve_pure, take_cell, SIZE_HINT_LIMIT,
+};
+use crate::list_access::RoundRobin;
 use crate::{
-    BuilderDataType, BuilderDeserializer, BuilderError, BuilderListAccessRef, BuilderMapAccessRef,
-    Closure,
+    BuilderDataType, BuilderDeserializer, BuilderError, BuilderListAccess, BuilderListAccessRef,
+    BuilderMapAccess, BuilderMapAccessRef, BuilderRepeatMapAccess, BuilderUnfoldAccess,
+    BuilderWhileAccess, Closure, DuplicateKeyPolicy,
 };
-use serde::de::Visitor;
+use alloc::borrow::Cow;
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use serde::de::{Error, Visitor};
 use serde::forward_to_deserialize_any;
-use std::borrow::Cow;
+use serde::Deserializer;
 
 pub struct BuilderDeserializerRef<'s, 'r, 'de> {
     pub(crate) closure: &'s mut Closure<'de>,
     pub(crate) data: &'r BuilderDataType<'de>,
 }
 
-impl<'s, 'r, 'de> serde::Deserializer<'de> for BuilderDeserializerRef<'s, 'r, 'de> {
-    type Error = BuilderError;
+impl<'s, 'r, 'de> BuilderDeserializerRef<'s, 'r, 'de> {
+    fn deserialize_integer<V>(self, visitor: V) -> Result<V::Value, BuilderError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Number(v) => visit_integral(*v, visitor),
+            BuilderDataType::Float32(v) => visit_integral(f64::from(*v), visitor),
+            BuilderDataType::String(s) if !self.closure.strict => visit_numeric_str(s, visitor),
+            BuilderDataType::Boolean(v) if !self.closure.strict => visitor.visit_u64(u64::from(*v)),
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_i64(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_i64(visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
 
-    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    /// Like `deserialize_integer`, but rejects a negative `Integer` instead of passing it on.
+    fn deserialize_unsigned<V>(self, visitor: V) -> Result<V::Value, BuilderError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Integer(value) if *value < 0 => {
+                Err(BuilderError::NegativeToUnsigned { value: *value })
+            }
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_u64(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_u64(visitor),
+            _ => self.deserialize_integer(visitor),
+        }
+    }
+
+    fn deserialize_node<V>(self, visitor: V) -> Result<V::Value, BuilderError>
     where
         V: Visitor<'de>,
     {
         match self.data {
-            BuilderDataType::Empty => todo!(),
+            BuilderDataType::Empty | BuilderDataType::Null => visitor.visit_unit(),
             BuilderDataType::Boolean(v) => visitor.visit_bool(*v),
             BuilderDataType::Integer(v) => visitor.visit_i64(*v),
             BuilderDataType::Unsigned(v) => visitor.visit_u64(*v),
             BuilderDataType::Number(v) => visitor.visit_f64(*v),
+            BuilderDataType::Float32(v) => visitor.visit_f32(*v),
             BuilderDataType::String(c) => match c {
-                Cow::Borrowed(v) => visitor.visit_borrowed_str(*v),
+                Cow::Borrowed(v) => visitor.visit_borrowed_str(v),
                 Cow::Owned(v) => visitor.visit_str(v),
             },
-            BuilderDataType::Map(v) => visitor.visit_map(BuilderMapAccessRef {
-                closure: self.closure,
-                data: v.iter(),
-                leftover: None,
-                size_hint: Some(v.len()),
-            }),
-            BuilderDataType::List(v) => visitor.visit_seq(BuilderListAccessRef {
-                closure: self.closure,
-                data: v.iter(),
-                index: 0,
-                size_hint: Some(v.len()),
-            }),
+            BuilderDataType::Bytes(c) => match c {
+                Cow::Borrowed(v) => visitor.visit_borrowed_bytes(v),
+                Cow::Owned(v) => visitor.visit_bytes(v),
+            },
+            BuilderDataType::Map(v)
+                if self.closure.duplicate_keys == DuplicateKeyPolicy::Merge
+                    || self.closure.group_keys =>
+            {
+                BuilderDeserializer {
+                    closure: self.closure,
+                    data: BuilderDataType::Map(v.clone()),
+                }
+                .deserialize_any(visitor)
+            }
+            BuilderDataType::Map(v) if self.closure.sort_maps => {
+                let size_hint = self.closure.map_len(v);
+                let mut entries: Vec<_> = v.iter().collect();
+                entries.sort_by_cached_key(|(k, _)| k.to_string());
+                visitor.visit_map(BuilderMapAccessRef {
+                    closure: self.closure,
+                    data: entries.into_iter(),
+                    leftover: None,
+                    size_hint,
+                    seen: BTreeSet::new(),
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Map(v) => {
+                let size_hint = self.closure.map_len(v);
+                visitor.visit_map(BuilderMapAccessRef {
+                    closure: self.closure,
+                    data: v.iter(),
+                    leftover: None,
+                    size_hint,
+                    seen: BTreeSet::new(),
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::List(v) => {
+                let size_hint = self.closure.list_len(v);
+                visitor.visit_seq(BuilderListAccessRef {
+                    closure: self.closure,
+                    data: v.iter(),
+                    index: 0,
+                    size_hint,
+                    pending: Vec::new(),
+                })
+            }
             BuilderDataType::Closure(v) => {
-                let mut closure = Closure {
-                    args: v
-                        .iter()
-                        .map(|a| self.closure.resolve_clone(a))
-                        .collect::<Result<Vec<_>, _>>()?,
-                    index: self.closure.index,
+                let Some(r) = v.first() else {
+                    return Err(BuilderError::EmptyClosure);
                 };
-                if let Some(r) = v.first() {
-                    BuilderDeserializerRef {
-                        closure: &mut closure,
-                        data: r,
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidFunctionArgument)
+                self.closure.check_args(r, v.len())?;
+                // The body is its own `Argument(0)` unevaluated, so it only runs as the body.
+                let args = core::iter::once(Ok(r.clone()))
+                    .chain(v[1..].iter().map(|a| self.closure.resolve_clone(a)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut closure = self.closure.nested(args);
+                BuilderDeserializerRef {
+                    closure: &mut closure,
+                    data: r,
                 }
+                .deserialize_any(visitor)
             }
-            BuilderDataType::Argument(a) => {
-                if let Some(p) = self.closure.args.get(*a).cloned() {
-                    BuilderDeserializer {
-                        closure: self.closure,
-                        data: p,
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidFunctionArgument)
+            BuilderDataType::Apply(body, v) => {
+                self.closure.check_args(body, v.len())?;
+                let args = v
+                    .iter()
+                    .map(|a| self.closure.resolve_clone(a))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut closure = self.closure.nested(args);
+                BuilderDeserializerRef {
+                    closure: &mut closure,
+                    data: body,
                 }
+                .deserialize_any(visitor)
             }
+            BuilderDataType::Argument(a) => {
+                let argument = self.closure.shared_argument(*a)?;
+                deserialize_indirect(self.closure, Indirect::Shared(argument), visitor)
+            }
+            BuilderDataType::ArgumentOr(a, d) => match self.closure.shared_argument(*a) {
+                Ok(argument) => {
+                    deserialize_indirect(self.closure, Indirect::Shared(argument), visitor)
+                }
+                Err(_) => BuilderDeserializerRef {
+                    closure: self.closure,
+                    data: d,
+                }
+                .deserialize_any(visitor),
+            },
             BuilderDataType::TakeFromArgument(a) => BuilderDeserializer {
                 data: self.closure.take_from_argument(*a)?,
                 closure: self.closure,
             }
             .deserialize_any(visitor),
-            BuilderDataType::Reference(r) => BuilderDeserializerRef {
+            BuilderDataType::Reference(r) => {
+                deserialize_indirect(self.closure, Indirect::Shared(r.clone()), visitor)
+            }
+            BuilderDataType::SelfReference(w) => match w.upgrade() {
+                Some(r) => deserialize_indirect(self.closure, Indirect::Shared(r), visitor),
+                None => Err(BuilderError::InvalidSelfRefrence),
+            },
+            BuilderDataType::SelfReferenceOr(w, d) => match w.upgrade() {
+                Some(r) => deserialize_indirect(self.closure, Indirect::Shared(r), visitor),
+                None => BuilderDeserializerRef {
+                    closure: self.closure,
+                    data: d,
+                }
+                .deserialize_any(visitor),
+            },
+            BuilderDataType::Store(r) => {
+                let data = read_cell(r)?;
+                // A nested cell may be this one, so release the borrow before reading it.
+                if data.has_cells() {
+                    let owned = data.clone();
+                    drop(data);
+                    return deserialize_indirect(self.closure, Indirect::Owned(owned), visitor);
+                }
+                BuilderDeserializerRef {
+                    closure: self.closure,
+                    data: &data,
+                }
+                .deserialize_any(visitor)
+            }
+            BuilderDataType::Take(r) => BuilderDeserializer {
                 closure: self.closure,
-                data: r.as_ref(),
+                data: take_cell(r)?,
             }
             .deserialize_any(visitor),
-            BuilderDataType::SelfReference(w) => {
-                if let Some(r) = w.upgrade() {
-                    BuilderDeserializerRef {
-                        closure: self.closure,
-                        data: r.as_ref(),
-                    }
-                    .deserialize_any(visitor)
-                } else {
-                    Err(BuilderError::InvalidSelfRefrence)
-                }
+            BuilderDataType::IfThenElse(v) => BuilderDeserializerRef {
+                data: self.closure.if_then_else_ref(v)?,
+                closure: self.closure,
             }
-            BuilderDataType::Store(r) => BuilderDeserializer {
+            .deserialize_any(visitor),
+            BuilderDataType::Switch(v) => BuilderDeserializerRef {
+                data: self.closure.switch_ref(v)?,
                 closure: self.closure,
-                data: r.as_ref().borrow().clone(),
             }
             .deserialize_any(visitor),
-            BuilderDataType::Take(r) => BuilderDeserializer {
+            BuilderDataType::Weighted(v) => BuilderDeserializerRef {
+                data: self.closure.weighted_ref(v)?,
                 closure: self.closure,
-                data: r.as_ref().borrow_mut().take_one(),
             }
             .deserialize_any(visitor),
-            BuilderDataType::IfThenElse(v) => BuilderDeserializerRef {
-                data: self.closure.if_then_else_ref(v)?,
+            BuilderDataType::Assert(v) => BuilderDeserializerRef {
+                data: self.closure.assert_ref(v)?,
                 closure: self.closure,
             }
             .deserialize_any(visitor),
             BuilderDataType::Repeat(v) => {
-                let mut it = v.iter();
-                let times = it.next().map_or(0, |r| r.to_unsigned());
+                let (times, body) = repeat_operands(v)?;
+                let times = self.closure.evaluate(times.clone())?.to_unsigned();
+                let body = resolve_pure(self.closure, body, times as usize)?;
                 visitor.visit_seq(BuilderListAccessRef {
                     closure: self.closure,
-                    data: it.cycle().take(times as usize),
-                    size_hint: Some(times as usize),
+                    data: body.iter().cycle().take(times as usize),
+                    size_hint: Some((times as usize).min(SIZE_HINT_LIMIT)),
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::RepeatMap(v) => {
+                let [count, key, value] = operands("RepeatMap", v)?;
+                visitor.visit_map(BuilderRepeatMapAccess {
+                    count: self.closure.evaluate(count.clone())?.to_unsigned() as usize,
+                    closure: self.closure,
+                    key,
+                    value,
                     index: 0,
                 })
             }
+            BuilderDataType::Interleave(v) => {
+                // Literal lists are iterated in place; only other operands are resolved.
+                let resolved = v
+                    .iter()
+                    .map(|l| match l {
+                        BuilderDataType::List(_) => Ok(Vec::new()),
+                        l => self.closure.resolve_list(l.clone()),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let lists = v
+                    .iter()
+                    .zip(&resolved)
+                    .map(|(l, r)| match l {
+                        BuilderDataType::List(l) => l.iter(),
+                        _ => r.iter(),
+                    })
+                    .collect::<Vec<_>>();
+                let data = RoundRobin::new(lists);
+                visitor.visit_seq(BuilderListAccessRef {
+                    size_hint: data.size_hint().1,
+                    closure: self.closure,
+                    data,
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Unfold(v) => {
+                let [seed, count, step] = operands("Unfold", v)?;
+                visitor.visit_seq(BuilderUnfoldAccess {
+                    current: self.closure.evaluate(seed.clone())?,
+                    count: self.closure.evaluate(count.clone())?.to_unsigned() as usize,
+                    closure: self.closure,
+                    step,
+                    index: 0,
+                })
+            }
+            BuilderDataType::While(v) => {
+                let [condition, body] = operands("While", v)?;
+                visitor.visit_seq(BuilderWhileAccess {
+                    closure: self.closure,
+                    condition,
+                    body,
+                    index: 0,
+                })
+            }
+            BuilderDataType::Sum(v) => BuilderDeserializer {
+                data: self.closure.sum(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Multiply(v) => BuilderDeserializer {
+                data: self.closure.multiply(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Divide(v) => BuilderDeserializer {
+                data: self.closure.divide(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::ParseInt(v) => BuilderDeserializer {
+                data: self.closure.parse_int(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::ParseFloat(v) => BuilderDeserializer {
+                data: self.closure.parse_float(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Path(v) => BuilderDeserializer {
+                data: self.closure.path(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Tap(v) => BuilderDeserializer {
+                data: self.closure.tap(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Quote(v) => BuilderDeserializer {
+                data: self.closure.quote(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Shuffle(n) => BuilderDeserializer {
+                data: self.closure.shuffle(n.as_ref().clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Replace(v) => BuilderDeserializer {
+                data: self.closure.replace(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Variant(v) => {
+                let [tag, payload] = operands("Variant", v)?;
+                BuilderDeserializer {
+                    closure: self.closure,
+                    data: BuilderDataType::Map(vec![(tag.clone(), payload.clone())]),
+                }
+                .deserialize_any(visitor)
+            }
+            BuilderDataType::Try(v) => {
+                let [primary, fallback] = operands("Try", v)?;
+                match self.closure.evaluate(primary.clone()) {
+                    Ok(data) => BuilderDeserializer {
+                        closure: self.closure,
+                        data,
+                    }
+                    .deserialize_any(visitor),
+                    Err(_) => BuilderDeserializerRef {
+                        closure: self.closure,
+                        data: fallback,
+                    }
+                    .deserialize_any(visitor),
+                }
+            }
+            BuilderDataType::Range(v) => {
+                let v = self.closure.range(v.clone())?;
+                let size_hint = Some(v.len());
+                visitor.visit_seq(BuilderListAccess {
+                    closure: self.closure,
+                    data: v.into_iter(),
+                    size_hint,
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Scan(v) => {
+                let v = self.closure.scan(v.clone())?;
+                let size_hint = Some(v.len());
+                visitor.visit_seq(BuilderListAccess {
+                    closure: self.closure,
+                    data: v.into_iter(),
+                    size_hint,
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Partition(v) => BuilderDeserializer {
+                data: self.closure.partition(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
             BuilderDataType::Index => visitor.visit_u64(self.closure.index as u64),
-            _ => todo!(),
+            BuilderDataType::Toggle(t) => visitor.visit_bool(t.replace_with(|b| !*b)),
+            BuilderDataType::ArgCount => visitor.visit_u64(self.closure.args.len() as u64),
+            BuilderDataType::Pad(v) => {
+                let v = self.closure.pad(v.clone())?;
+                let size_hint = Some(v.len());
+                visitor.visit_seq(BuilderListAccess {
+                    closure: self.closure,
+                    data: v.into_iter(),
+                    size_hint,
+                    index: 0,
+                    pending: Vec::new(),
+                })
+            }
+            BuilderDataType::Let(v) => {
+                let body = self.closure.bind_ref(v)?;
+                let result = BuilderDeserializerRef {
+                    closure: &mut *self.closure,
+                    data: body,
+                }
+                .deserialize_any(visitor);
+                self.closure.bindings.pop();
+                result
+            }
+            BuilderDataType::DefineFn(v) => {
+                let body = self.closure.define_ref(v)?;
+                let result = BuilderDeserializerRef {
+                    closure: &mut *self.closure,
+                    data: body,
+                }
+                .deserialize_any(visitor);
+                self.closure.functions.pop();
+                result
+            }
+            BuilderDataType::CallFn(v) => {
+                let (function, args) = self.closure.call_ref(v)?;
+                let mut closure = self.closure.nested(args);
+                BuilderDeserializerRef {
+                    closure: &mut closure,
+                    data: &function,
+                }
+                .deserialize_any(visitor)
+            }
+            BuilderDataType::Var(n) => BuilderDeserializer {
+                data: self.closure.var(n)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Lazy(n) | BuilderDataType::Comment(n, _) => BuilderDeserializerRef {
+                closure: self.closure,
+                data: n,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "std-time")]
+            BuilderDataType::Now => visitor.visit_u64((self.closure.clock)()),
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(n) => BuilderDeserializer {
+                data: self.closure.env(n)?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            BuilderDataType::Duration(v) => BuilderDeserializer {
+                data: self.closure.duration(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) => BuilderDeserializer {
+                data: self.closure.matches(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Capture(v) => BuilderDeserializer {
+                data: self.closure.capture_group(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => BuilderDeserializer {
+                data: self.closure.format_date(v.clone())?,
+                closure: self.closure,
+            }
+            .deserialize_any(visitor),
+            b => Err(BuilderError::UnsupportedVariant(b.kind())),
+        }
+    }
+}
+
+impl<'s, 'r, 'de> serde::Deserializer<'de> for BuilderDeserializerRef<'s, 'r, 'de> {
+    type Error = BuilderError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let BuilderDeserializerRef { closure, data } = self;
+        if closure.depth >= closure.recursion_limit {
+            return Err(BuilderError::RecursionLimit(closure.recursion_limit));
+        }
+        let stats = closure.stats.clone();
+        if let Some(stats) = &stats {
+            stats.borrow_mut().enter(data.kind());
+        }
+        closure.depth += 1;
+        let result = BuilderDeserializerRef {
+            closure: &mut *closure,
+            data,
+        }
+        .deserialize_node(visitor);
+        closure.depth -= 1;
+        if let Some(stats) = &stats {
+            stats.borrow_mut().leave();
+        }
+        result
+    }
+
+    deserialize_integers! {
+        deserialize_integer:
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+    }
+
+    deserialize_integers! {
+        deserialize_unsigned:
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Float32(v) => visitor.visit_f32(*v),
+            BuilderDataType::Number(v) => visitor.visit_f32(narrow_f32(*v)?),
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_f32(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_f32(visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Empty => visitor.visit_none(),
+            BuilderDataType::Null => visitor.visit_unit(),
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_option(visitor))
+            }
+            BuilderDataType::SelfReferenceOr(w, d) if w.strong_count() == 0 => {
+                BuilderDeserializerRef {
+                    closure: self.closure,
+                    data: d,
+                }
+                .deserialize_option(visitor)
+            }
+            BuilderDataType::Take(r) => BuilderDeserializer {
+                closure: self.closure,
+                data: take_cell(r)?,
+            }
+            .deserialize_option(visitor),
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_option(visitor),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.closure.strict {
+            return self.deserialize_any(visitor);
+        }
+        match self.data {
+            BuilderDataType::Boolean(v) => visitor.visit_bool(*v),
+            BuilderDataType::String(s) if s.eq_ignore_ascii_case("true") => {
+                visitor.visit_bool(true)
+            }
+            BuilderDataType::String(s) if s.eq_ignore_ascii_case("false") => {
+                visitor.visit_bool(false)
+            }
+            data @ (BuilderDataType::Empty
+            | BuilderDataType::Integer(_)
+            | BuilderDataType::Unsigned(_)
+            | BuilderDataType::Number(_)
+            | BuilderDataType::Float32(_)
+            | BuilderDataType::String(_)) => visitor.visit_bool(data.check_true()),
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_bool(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_bool(visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        if self.closure.strict {
+            return self.deserialize_any(visitor);
+        }
+        match self.data {
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_str(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_str(visitor),
+            data => match stringify_scalar(data) {
+                Some(s) => visitor.visit_string(s),
+                None => self.deserialize_any(visitor),
+            },
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Empty => {
+                visitor.visit_seq(BuilderListAccess::new(self.closure, core::iter::empty()))
+            }
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_seq(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_seq(visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Empty => {
+                visitor.visit_map(BuilderMapAccess::new(self.closure, core::iter::empty()))
+            }
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_map(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_map(visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::List(v) if self.closure.list_len(v).is_some_and(|n| n != len) => {
+                Err(BuilderError::ArrayLengthMismatch {
+                    expected: len,
+                    found: self.closure.list_len(v).unwrap_or(len),
+                })
+            }
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_tuple(len, visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_tuple(len, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::List(v) if self.closure.list_len(v).is_some_and(|n| n != len) => {
+                Err(BuilderError::invalid_length(
+                    self.closure.list_len(v).unwrap_or(len),
+                    &format!("tuple struct {name} of length {len}").as_str(),
+                ))
+            }
+            BuilderDataType::Argument(a) => with_argument(self.closure, *a, |d| {
+                d.deserialize_tuple_struct(name, len, visitor)
+            }),
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_tuple_struct(name, len, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Map(v) => match self.closure.field_order(v, fields)? {
+                Some(order) => visitor.visit_map(BuilderMapAccessRef {
+                    closure: self.closure,
+                    size_hint: Some(order.len()),
+                    data: order.into_iter().map(|i| &v[i]),
+                    leftover: None,
+                    seen: BTreeSet::new(),
+                    pending: Vec::new(),
+                }),
+                None => self.deserialize_any(visitor),
+            },
+            BuilderDataType::Argument(a) => with_argument(self.closure, *a, |d| {
+                d.deserialize_struct(name, fields, visitor)
+            }),
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_struct(name, fields, visitor),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        BuilderDeserializer {
+            closure: self.closure,
+            data: self.data.clone(),
+        }
+        .deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data {
+            BuilderDataType::Integer(v) if *v >= 0 => visitor.visit_u64(*v as u64),
+            BuilderDataType::Argument(a) => {
+                with_argument(self.closure, *a, |d| d.deserialize_identifier(visitor))
+            }
+            data if data.is_resolvable() => BuilderDeserializer {
+                data: self.closure.resolve_clone(data)?,
+                closure: self.closure,
+            }
+            .deserialize_identifier(visitor),
+            _ => self.deserialize_any(visitor),
         }
     }
     forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option unit unit_struct newtype_struct seq tuple
-        tuple_struct map struct enum identifier ignored_any
+        f64 char bytes byte_buf
+        unit unit_struct newtype_struct ignored_any
     }
 }
@@ -1,32 +1,292 @@
 use crate::datatype::BuilderDataType;
 use crate::errors::BuilderError;
+use crate::map_access::DuplicateKeyPolicy;
+use crate::rng::Rng;
+use crate::stats::Stats;
+use alloc::borrow::{Cow, ToOwned};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::{Ref, RefCell, RefMut};
+#[cfg(feature = "std-env")]
+use std::collections::HashMap;
+
+/// Upper bound on the number of elements a `While` loop may produce.
+pub(crate) const DEFAULT_ITERATION_LIMIT: usize = 1 << 16;
+
+/// Upper bound on how deeply nodes may nest while deserializing, so a deep or self-referencing
+/// tree fails instead of overflowing the stack.
+pub(crate) const DEFAULT_RECURSION_LIMIT: usize = 64;
+
+/// Upper bound on the `size_hint` of generated sequences and maps, so a huge count does not make
+/// visitors preallocate for elements that were never built.
+pub(crate) const SIZE_HINT_LIMIT: usize = 1 << 12;
+
+/// Callback receiving the label and value of each evaluated `Tap`.
+pub(crate) type TapFn = dyn Fn(&str, &str);
 
 pub struct Closure<'de> {
-    pub(crate) args: Vec<BuilderDataType<'de>>,
+    pub(crate) args: Vec<Rc<BuilderDataType<'de>>>,
     pub(crate) index: usize,
+    pub(crate) strict: bool,
+    pub(crate) iteration_limit: usize,
+    pub(crate) recursion_limit: usize,
+    /// Nodes currently being deserialized, carried into nested scopes.
+    pub(crate) depth: usize,
+    pub(crate) bindings: Vec<(Cow<'de, str>, BuilderDataType<'de>)>,
+    pub(crate) functions: Vec<(Cow<'de, str>, Rc<BuilderDataType<'de>>)>,
+    pub(crate) duplicate_keys: DuplicateKeyPolicy,
+    pub(crate) sort_maps: bool,
+    pub(crate) group_keys: bool,
+    pub(crate) skip_empty: bool,
+    pub(crate) check_arity: bool,
+    pub(crate) tap: Option<Rc<TapFn>>,
+    pub(crate) stats: Option<Rc<RefCell<Stats>>>,
+    /// Shared with nested scopes so every draw advances the same sequence. Starts from seed 0
+    /// unless set from `DeserializerConfig::seed`.
+    pub(crate) rng: Rc<RefCell<Rng>>,
+    #[cfg(feature = "std-time")]
+    pub(crate) clock: fn() -> u64,
+    #[cfg(feature = "std-env")]
+    pub(crate) env: Option<Rc<HashMap<String, String>>>,
+}
+
+#[cfg(feature = "std-time")]
+pub(crate) fn system_clock() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Checks that `v` holds exactly `N` operands for `op`.
+pub(crate) fn operands<'a, 'de, const N: usize>(
+    op: &'static str,
+    v: &'a [BuilderDataType<'de>],
+) -> Result<&'a [BuilderDataType<'de>; N], BuilderError> {
+    v.try_into().map_err(|_| BuilderError::WrongArity {
+        op,
+        expected: N,
+        found: v.len(),
+    })
+}
+
+/// Owned counterpart of [`operands`].
+pub(crate) fn into_operands<'de, const N: usize>(
+    op: &'static str,
+    v: Vec<BuilderDataType<'de>>,
+) -> Result<[BuilderDataType<'de>; N], BuilderError> {
+    let found = v.len();
+    v.try_into().map_err(|_| BuilderError::WrongArity {
+        op,
+        expected: N,
+        found,
+    })
+}
+
+/// Checks the operand count of an operation whose trailing operands are optional, reporting the
+/// bound that was missed.
+pub(crate) fn operand_count(
+    op: &'static str,
+    found: usize,
+    min: usize,
+    max: usize,
+) -> Result<(), BuilderError> {
+    let expected = if found < min {
+        min
+    } else if found > max {
+        max
+    } else {
+        return Ok(());
+    };
+    Err(BuilderError::WrongArity {
+        op,
+        expected,
+        found,
+    })
+}
+
+/// Splits `Repeat` operands into the count and the non-empty body cycled through.
+pub(crate) fn repeat_operands<'a, 'de>(
+    v: &'a [BuilderDataType<'de>],
+) -> Result<(&'a BuilderDataType<'de>, &'a [BuilderDataType<'de>]), BuilderError> {
+    match v {
+        [times, body @ ..] if !body.is_empty() => Ok((times, body)),
+        _ => Err(BuilderError::WrongArity {
+            op: "Repeat",
+            expected: 2,
+            found: v.len(),
+        }),
+    }
+}
+
+/// Resolves a `Repeat` body once when it is pure and has something to resolve, so its elements
+/// are not recomputed on every cycle.
+pub(crate) fn resolve_pure<'a, 'de>(
+    closure: &mut Closure<'de>,
+    body: &'a [BuilderDataType<'de>],
+    times: usize,
+) -> Result<Cow<'a, [BuilderDataType<'de>]>, BuilderError> {
+    if times < body.len()
+        || !body.iter().any(BuilderDataType::is_resolvable)
+        || !body.iter().all(BuilderDataType::is_pure)
+    {
+        return Ok(Cow::Borrowed(body));
+    }
+    body.iter()
+        .map(|b| {
+            let b = closure.resolve_clone(b)?;
+            closure.evaluate(b)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Cow::Owned)
+}
+
+/// Borrows the cell of a `Store`, failing instead of panicking while a `Take` holds it.
+pub(crate) fn read_cell<'a, 'de>(
+    cell: &'a RefCell<BuilderDataType<'de>>,
+) -> Result<Ref<'a, BuilderDataType<'de>>, BuilderError> {
+    cell.try_borrow().map_err(|_| BuilderError::BorrowConflict)
+}
+
+/// Takes one value from the cell of a `Take`, failing instead of panicking while it is read.
+pub(crate) fn take_cell<'de>(
+    cell: &RefCell<BuilderDataType<'de>>,
+) -> Result<BuilderDataType<'de>, BuilderError> {
+    Ok(cell
+        .try_borrow_mut()
+        .map_err(|_| BuilderError::BorrowConflict)?
+        .take_one())
+}
+
+impl<'de> Default for Closure<'de> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'de> Closure<'de> {
-    pub(crate) fn get_argument(&self, a: usize) -> Result<&BuilderDataType<'de>, BuilderError> {
-        if let Some(a) = self.args.get(a) {
-            Ok(a)
-        } else {
-            Err(BuilderError::InvalidFunctionArgument)
+    /// Creates an empty top-level scope with default settings.
+    pub fn new() -> Self {
+        Closure {
+            args: Vec::new(),
+            index: 0,
+            strict: false,
+            iteration_limit: DEFAULT_ITERATION_LIMIT,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            depth: 0,
+            bindings: Vec::new(),
+            functions: Vec::new(),
+            duplicate_keys: DuplicateKeyPolicy::Last,
+            sort_maps: false,
+            group_keys: false,
+            skip_empty: false,
+            check_arity: false,
+            tap: None,
+            stats: None,
+            rng: Rc::new(RefCell::new(Rng::new(0))),
+            #[cfg(feature = "std-time")]
+            clock: system_clock,
+            #[cfg(feature = "std-env")]
+            env: None,
+        }
+    }
+    /// Creates a closure scope with new arguments, keeping the settings of `self`.
+    pub(crate) fn nested(&self, args: Vec<BuilderDataType<'de>>) -> Self {
+        Closure {
+            args: args.into_iter().map(Rc::new).collect(),
+            index: self.index,
+            strict: self.strict,
+            iteration_limit: self.iteration_limit,
+            recursion_limit: self.recursion_limit,
+            depth: self.depth,
+            bindings: self.bindings.clone(),
+            functions: self.functions.clone(),
+            duplicate_keys: self.duplicate_keys,
+            sort_maps: self.sort_maps,
+            group_keys: self.group_keys,
+            skip_empty: self.skip_empty,
+            check_arity: self.check_arity,
+            tap: self.tap.clone(),
+            stats: self.stats.clone(),
+            rng: self.rng.clone(),
+            #[cfg(feature = "std-time")]
+            clock: self.clock,
+            #[cfg(feature = "std-env")]
+            env: self.env.clone(),
+        }
+    }
+    fn count_clone(&self) {
+        if let Some(stats) = &self.stats {
+            stats.borrow_mut().clones += 1;
         }
     }
     pub(crate) fn clone_argument(&self, a: usize) -> Result<BuilderDataType<'de>, BuilderError> {
         if let Some(a) = self.args.get(a) {
-            Ok(a.clone())
+            self.count_clone();
+            Ok(a.as_ref().clone())
         } else {
             Err(BuilderError::InvalidFunctionArgument)
         }
     }
+    /// Shares an argument instead of cloning its subtree.
+    pub(crate) fn shared_argument(
+        &self,
+        a: usize,
+    ) -> Result<Rc<BuilderDataType<'de>>, BuilderError> {
+        self.args
+            .get(a)
+            .cloned()
+            .ok_or(BuilderError::InvalidFunctionArgument)
+    }
+    pub(crate) fn argument_or(
+        &self,
+        a: usize,
+        default: BuilderDataType<'de>,
+    ) -> BuilderDataType<'de> {
+        match self.args.get(a) {
+            Some(a) => {
+                self.count_clone();
+                a.as_ref().clone()
+            }
+            None => default,
+        }
+    }
+    /// Binds `body` to a copy of the current arguments.
+    fn capture(&self, body: BuilderDataType<'de>) -> BuilderDataType<'de> {
+        let args = self
+            .args
+            .iter()
+            .map(|a| {
+                self.count_clone();
+                a.as_ref().clone()
+            })
+            .collect();
+        BuilderDataType::Apply(Box::new(body), args)
+    }
+    /// Fails early when `check_arity` is on and `body` reads an argument past the `provided`
+    /// ones.
+    pub(crate) fn check_args(
+        &self,
+        body: &BuilderDataType<'de>,
+        provided: usize,
+    ) -> Result<(), BuilderError> {
+        if !self.check_arity {
+            return Ok(());
+        }
+        match body.arguments_needed() {
+            needed if needed > provided => Err(BuilderError::ArgCountMismatch { needed, provided }),
+            _ => Ok(()),
+        }
+    }
     pub(crate) fn take_from_argument(
         &mut self,
         a: usize,
     ) -> Result<BuilderDataType<'de>, BuilderError> {
         if let Some(a) = self.args.get_mut(a) {
-            Ok(a.take_one())
+            Ok(Rc::make_mut(a).take_one())
         } else {
             Err(BuilderError::InvalidFunctionArgument)
         }
@@ -37,8 +297,40 @@ impl<'de> Closure<'de> {
     ) -> Result<BuilderDataType<'de>, BuilderError> {
         match b {
             BuilderDataType::Argument(a) => self.clone_argument(a),
+            BuilderDataType::ArgumentOr(a, d) => Ok(self.argument_or(a, *d)),
             BuilderDataType::TakeFromArgument(a) => self.take_from_argument(a),
             BuilderDataType::IfThenElse(v) => self.if_then_else(v),
+            BuilderDataType::Pad(v) => self.pad(v).map(BuilderDataType::List),
+            BuilderDataType::Var(n) => self.var(&n),
+            BuilderDataType::Switch(v) => self.switch(v),
+            BuilderDataType::Weighted(v) => self.weighted(v),
+            BuilderDataType::Sum(v) => self.sum(v),
+            BuilderDataType::Multiply(v) => self.multiply(v),
+            BuilderDataType::Scan(v) => self.scan(v).map(BuilderDataType::List),
+            BuilderDataType::Range(v) => self.range(v).map(BuilderDataType::List),
+            BuilderDataType::Partition(v) => self.partition(v),
+            BuilderDataType::Assert(v) => self.assert(v),
+            BuilderDataType::Divide(v) => self.divide(v),
+            BuilderDataType::Try(v) => self.attempt(v),
+            BuilderDataType::ParseInt(v) => self.parse_int(v),
+            BuilderDataType::ParseFloat(v) => self.parse_float(v),
+            BuilderDataType::Tap(v) => self.tap(v),
+            BuilderDataType::Quote(v) => self.quote(v),
+            BuilderDataType::Replace(v) => self.replace(v),
+            BuilderDataType::Path(v) => self.path(v),
+            BuilderDataType::Lazy(n) => Ok(self.capture(*n)),
+            BuilderDataType::Comment(n, _) => self.resolve(*n),
+            BuilderDataType::Shuffle(n) => self.shuffle(*n),
+            BuilderDataType::Toggle(t) => Ok(BuilderDataType::Boolean(t.replace_with(|b| !*b))),
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(n) => self.env(&n),
+            BuilderDataType::Duration(v) => self.duration(v),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) => self.matches(v),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Capture(v) => self.capture_group(v),
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => self.format_date(v),
             b => Ok(b),
         }
     }
@@ -46,21 +338,71 @@ impl<'de> Closure<'de> {
         &mut self,
         b: &BuilderDataType<'de>,
     ) -> Result<BuilderDataType<'de>, BuilderError> {
+        if !matches!(
+            b,
+            BuilderDataType::Argument(_)
+                | BuilderDataType::ArgumentOr(..)
+                | BuilderDataType::TakeFromArgument(_)
+        ) {
+            self.count_clone();
+        }
         match b {
             BuilderDataType::Argument(a) => self.clone_argument(*a),
+            BuilderDataType::ArgumentOr(a, d) => Ok(self.argument_or(*a, d.as_ref().clone())),
             BuilderDataType::TakeFromArgument(a) => self.take_from_argument(*a),
             BuilderDataType::IfThenElse(v) => self.if_then_else_ref(v).cloned(),
+            BuilderDataType::Pad(v) => self.pad(v.clone()).map(BuilderDataType::List),
+            BuilderDataType::Var(n) => self.var(n),
+            BuilderDataType::Switch(v) => self.switch_ref(v).cloned(),
+            BuilderDataType::Weighted(v) => self.weighted_ref(v).cloned(),
+            BuilderDataType::Sum(v) => self.sum(v.clone()),
+            BuilderDataType::Multiply(v) => self.multiply(v.clone()),
+            BuilderDataType::Scan(v) => self.scan(v.clone()).map(BuilderDataType::List),
+            BuilderDataType::Range(v) => self.range(v.clone()).map(BuilderDataType::List),
+            BuilderDataType::Partition(v) => self.partition(v.clone()),
+            BuilderDataType::Assert(v) => self.assert_ref(v).cloned(),
+            BuilderDataType::Divide(v) => self.divide(v.clone()),
+            BuilderDataType::Try(v) => self.attempt(v.clone()),
+            BuilderDataType::ParseInt(v) => self.parse_int(v.clone()),
+            BuilderDataType::ParseFloat(v) => self.parse_float(v.clone()),
+            BuilderDataType::Tap(v) => self.tap(v.clone()),
+            BuilderDataType::Quote(v) => self.quote(v.clone()),
+            BuilderDataType::Replace(v) => self.replace(v.clone()),
+            BuilderDataType::Path(v) => self.path(v.clone()),
+            BuilderDataType::Lazy(n) => Ok(self.capture(n.as_ref().clone())),
+            BuilderDataType::Comment(n, _) => self.resolve(n.as_ref().clone()),
+            BuilderDataType::Shuffle(n) => self.shuffle(n.as_ref().clone()),
+            BuilderDataType::Toggle(t) => Ok(BuilderDataType::Boolean(t.replace_with(|b| !*b))),
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(n) => self.env(n),
+            BuilderDataType::Duration(v) => self.duration(v.clone()),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) => self.matches(v.clone()),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Capture(v) => self.capture_group(v.clone()),
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => self.format_date(v.clone()),
             b => Ok(b.clone()),
         }
     }
+    /// Evaluates a condition exactly once, so a `Take` reached from it is consumed once.
     pub(crate) fn resolve_to_bool(
         &mut self,
         b: &BuilderDataType<'de>,
     ) -> Result<bool, BuilderError> {
         Ok(match b {
-            BuilderDataType::Argument(a) => self.get_argument(*a)?.check_true(),
-            BuilderDataType::TakeFromArgument(a) => self.take_from_argument(*a)?.check_true(),
-            BuilderDataType::IfThenElse(v) => self.if_then_else_ref(v)?.check_true(),
+            BuilderDataType::IfThenElse(v) => {
+                let branch = self.if_then_else_ref(v)?;
+                self.resolve_to_bool(branch)?
+            }
+            b if b.is_resolvable() => {
+                let b = self.resolve_clone(b)?;
+                self.evaluate(b)?.check_true()
+            }
+            BuilderDataType::Index => self.index != 0,
+            BuilderDataType::Store(_) | BuilderDataType::Take(_) => {
+                self.evaluate(b.clone())?.check_true()
+            }
             b => b.check_true(),
         })
     }
@@ -68,16 +410,7 @@ impl<'de> Closure<'de> {
         &mut self,
         v: &'a Vec<BuilderDataType<'de>>,
     ) -> Result<&'a BuilderDataType<'de>, BuilderError> {
-        let mut i = v.iter();
-        let Some(condition) = i.next() else {
-            return Err(BuilderError::InvalidFunctionArgument);
-        };
-        let Some(if_true) = i.next() else {
-            return Err(BuilderError::InvalidFunctionArgument);
-        };
-        let Some(if_false) = i.next() else {
-            return Err(BuilderError::InvalidFunctionArgument);
-        };
+        let [condition, if_true, if_false] = operands("IfThenElse", v)?;
         if self.resolve_to_bool(condition)? {
             Ok(if_true)
         } else {
@@ -88,20 +421,863 @@ impl<'de> Closure<'de> {
         &mut self,
         v: Vec<BuilderDataType<'de>>,
     ) -> Result<BuilderDataType<'de>, BuilderError> {
-        let mut i = v.into_iter();
-        let Some(condition) = i.next() else {
+        let [condition, if_true, if_false] = into_operands("IfThenElse", v)?;
+        if self.evaluate(condition)?.check_true() {
+            Ok(if_true)
+        } else {
+            Ok(if_false)
+        }
+    }
+    /// Checks the condition of a `[condition, value, message?]` assertion and returns its value.
+    pub(crate) fn assert_ref<'a>(
+        &mut self,
+        v: &'a [BuilderDataType<'de>],
+    ) -> Result<&'a BuilderDataType<'de>, BuilderError> {
+        operand_count("Assert", v.len(), 2, 3)?;
+        if self.resolve_to_bool(&v[0])? {
+            return Ok(&v[1]);
+        }
+        Err(BuilderError::AssertionFailed(match v.get(2) {
+            Some(message) => self.resolve_clone(message)?.to_string().into_owned(),
+            None => "assertion failed".to_owned(),
+        }))
+    }
+    pub(crate) fn assert(
+        &mut self,
+        mut v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        self.assert_ref(&v)?;
+        Ok(v.swap_remove(1))
+    }
+    /// Picks the operand of a `[selector, key, value, .., default]` switch whose key matches the
+    /// selector by `to_string()`, or the trailing default.
+    fn switch_index(&mut self, v: &[BuilderDataType<'de>]) -> Result<usize, BuilderError> {
+        operand_count("Switch", v.len(), 2, usize::MAX)?;
+        let (selector, cases) = (&v[0], &v[1..]);
+        if cases.len() % 2 == 0 {
             return Err(BuilderError::InvalidFunctionArgument);
-        };
-        let Some(if_true) = i.next() else {
+        }
+        let selector = self.resolve_clone(selector)?.to_string();
+        for (i, key) in cases.iter().enumerate().step_by(2).take(cases.len() / 2) {
+            if self.resolve_clone(key)?.to_string() == selector {
+                return Ok(i + 2);
+            }
+        }
+        Ok(v.len() - 1)
+    }
+    pub(crate) fn switch_ref<'a>(
+        &mut self,
+        v: &'a [BuilderDataType<'de>],
+    ) -> Result<&'a BuilderDataType<'de>, BuilderError> {
+        let i = self.switch_index(v)?;
+        Ok(&v[i])
+    }
+    pub(crate) fn switch(
+        &mut self,
+        mut v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let i = self.switch_index(&v)?;
+        Ok(v.swap_remove(i))
+    }
+    /// Picks a value of `[weight, value, ...]` with probability proportional to its weight.
+    fn weighted_index(&mut self, v: &[BuilderDataType<'de>]) -> Result<usize, BuilderError> {
+        operand_count("Weighted", v.len(), 2, usize::MAX)?;
+        if !v.len().is_multiple_of(2) {
             return Err(BuilderError::InvalidFunctionArgument);
+        }
+        let mut weights = Vec::with_capacity(v.len() / 2);
+        for weight in v.iter().step_by(2) {
+            let weight = self.evaluate(weight.clone())?.to_float();
+            if !(weight >= 0.0 && weight.is_finite()) {
+                return Err(BuilderError::InvalidFunctionArgument);
+            }
+            weights.push(weight);
+        }
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return Err(BuilderError::ZeroTotalWeight);
+        }
+        let mut target = self.rng().unit() * total;
+        for (i, weight) in weights.iter().enumerate() {
+            if target < *weight {
+                return Ok(2 * i + 1);
+            }
+            target -= weight;
+        }
+        // Rounding can leave `target` just past the last weight; fall back to the last value
+        // that has any weight.
+        let last = weights.iter().rposition(|w| *w > 0.0).unwrap_or_default();
+        Ok(2 * last + 1)
+    }
+    pub(crate) fn weighted_ref<'a>(
+        &mut self,
+        v: &'a [BuilderDataType<'de>],
+    ) -> Result<&'a BuilderDataType<'de>, BuilderError> {
+        let i = self.weighted_index(v)?;
+        Ok(&v[i])
+    }
+    pub(crate) fn weighted(
+        &mut self,
+        mut v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let i = self.weighted_index(&v)?;
+        Ok(v.swap_remove(i))
+    }
+    /// Resolves `b` down to a plain value, draining `Take` and reading through `Reference`,
+    /// `SelfReference` and `Store`.
+    pub(crate) fn evaluate(
+        &mut self,
+        b: BuilderDataType<'de>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        match self.resolve(b)? {
+            BuilderDataType::Reference(r) => {
+                self.evaluate(Rc::try_unwrap(r).unwrap_or_else(|r| r.as_ref().clone()))
+            }
+            BuilderDataType::SelfReference(w) => match w.upgrade() {
+                Some(r) => self.evaluate(r.as_ref().clone()),
+                None => Err(BuilderError::InvalidSelfRefrence),
+            },
+            BuilderDataType::SelfReferenceOr(w, d) => match w.upgrade() {
+                Some(r) => self.evaluate(r.as_ref().clone()),
+                None => self.evaluate(*d),
+            },
+            BuilderDataType::Store(r) => {
+                let inner = read_cell(&r)?.clone();
+                self.evaluate(inner)
+            }
+            BuilderDataType::Take(r) => {
+                let inner = take_cell(&r)?;
+                self.evaluate(inner)
+            }
+            BuilderDataType::Index => Ok(BuilderDataType::Unsigned(self.index as u64)),
+            BuilderDataType::ArgCount => Ok(BuilderDataType::Unsigned(self.args.len() as u64)),
+            BuilderDataType::DefineFn(v) => {
+                let body = self.define(v)?;
+                let result = self.evaluate(body);
+                self.functions.pop();
+                result
+            }
+            BuilderDataType::CallFn(v) => {
+                let (function, args) = self.call(v)?;
+                self.nested(args).evaluate(function.as_ref().clone())
+            }
+            #[cfg(feature = "std-time")]
+            BuilderDataType::Now => Ok(BuilderDataType::Unsigned((self.clock)())),
+            b if b.is_resolvable() => self.evaluate(b),
+            b => Ok(b),
+        }
+    }
+    /// Applies the duplicate key policy to a map key, returning the evaluated key or `None` when
+    /// the entry should be skipped.
+    pub(crate) fn admit_key(
+        &mut self,
+        seen: &mut BTreeSet<String>,
+        key: BuilderDataType<'de>,
+    ) -> Result<Option<BuilderDataType<'de>>, BuilderError> {
+        if self.duplicate_keys.yields_all() {
+            return Ok(Some(key));
+        }
+        let key = self.evaluate(key)?;
+        let name = key.to_string().into_owned();
+        if seen.contains(&name) {
+            return match self.duplicate_keys {
+                DuplicateKeyPolicy::Error => Err(BuilderError::DuplicateKey(name)),
+                _ => Ok(None),
+            };
+        }
+        seen.insert(name);
+        Ok(Some(key))
+    }
+    /// Folds repeated keys of a `Map` into their first entry, deep-merging values that are both
+    /// maps. Keys are evaluated, and values only when their key repeats.
+    pub(crate) fn merge_entries(
+        &mut self,
+        entries: Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>,
+    ) -> Result<Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>, BuilderError> {
+        let mut merged: Vec<(BuilderDataType<'de>, BuilderDataType<'de>)> = Vec::new();
+        let mut positions: BTreeMap<String, usize> = BTreeMap::new();
+        for (key, value) in entries {
+            if let BuilderDataType::Spread(_) = key {
+                merged.push((key, value));
+                continue;
+            }
+            let key = self.evaluate(key)?;
+            let name = key.to_string().into_owned();
+            if let Some(&i) = positions.get(&name) {
+                let previous = core::mem::replace(&mut merged[i].1, BuilderDataType::Empty);
+                merged[i].1 = match (self.evaluate(previous)?, self.evaluate(value)?) {
+                    (BuilderDataType::Map(mut a), BuilderDataType::Map(b)) => {
+                        a.extend(b);
+                        BuilderDataType::Map(self.merge_entries(a)?)
+                    }
+                    (_, b) => b,
+                };
+            } else {
+                positions.insert(name, merged.len());
+                merged.push((key, value));
+            }
+        }
+        Ok(merged)
+    }
+    /// Gathers the values of consecutive entries with equal keys into one `List` value. Keys are
+    /// evaluated; an entry whose key does not repeat keeps its value as is.
+    pub(crate) fn group_entries(
+        &mut self,
+        entries: Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>,
+    ) -> Result<Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>, BuilderError> {
+        let mut groups: Vec<(
+            Option<String>,
+            BuilderDataType<'de>,
+            Vec<BuilderDataType<'de>>,
+        )> = Vec::new();
+        for (key, value) in entries {
+            if let BuilderDataType::Spread(_) = key {
+                groups.push((None, key, vec![value]));
+                continue;
+            }
+            let key = self.evaluate(key)?;
+            let name = Some(key.to_string().into_owned());
+            match groups.last_mut() {
+                Some((last, _, values)) if *last == name => values.push(value),
+                _ => groups.push((name, key, vec![value])),
+            }
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(_, key, mut values)| match values.len() {
+                1 => (key, values.pop().unwrap()),
+                _ => (key, BuilderDataType::List(values)),
+            })
+            .collect())
+    }
+    /// Indexes the entries of a struct-backed `Map` by key once and returns their positions with
+    /// the declared `fields` first, followed by unknown keys in map order. Ordering `n` entries
+    /// for `m` fields costs O((n + m) log n). Repeated keys keep one entry according to the
+    /// duplicate key policy. A numeric key `i` names `fields[i]`, so a sparse numeric-keyed map
+    /// fills the fields at those positions. Returns `None` unless every key is a plain `String` or
+    /// an in-range field index, as computed keys may have side effects when evaluated, or when
+    /// keys are grouped.
+    pub(crate) fn field_order(
+        &self,
+        entries: &[(BuilderDataType<'de>, BuilderDataType<'de>)],
+        fields: &[&str],
+    ) -> Result<Option<Vec<usize>>, BuilderError> {
+        if self.group_keys {
+            return Ok(None);
+        }
+        let mut index: BTreeMap<&str, usize> = BTreeMap::new();
+        for (i, (key, _)) in entries.iter().enumerate() {
+            let position = match key {
+                BuilderDataType::Integer(i) => usize::try_from(*i).ok(),
+                BuilderDataType::Unsigned(i) => usize::try_from(*i).ok(),
+                _ => None,
+            };
+            let key: &str = match (key, position.and_then(|p| fields.get(p))) {
+                (BuilderDataType::String(key), _) => key,
+                (_, Some(field)) => field,
+                _ => return Ok(None),
+            };
+            match self.duplicate_keys {
+                DuplicateKeyPolicy::Last => {
+                    index.insert(key, i);
+                }
+                DuplicateKeyPolicy::First => {
+                    index.entry(key).or_insert(i);
+                }
+                DuplicateKeyPolicy::Error => {
+                    if index.insert(key, i).is_some() {
+                        return Err(BuilderError::DuplicateKey(key.to_owned()));
+                    }
+                }
+                DuplicateKeyPolicy::Merge => return Ok(None),
+            }
+        }
+        let mut order: Vec<usize> = fields.iter().filter_map(|f| index.remove(f)).collect();
+        let mut unknown: Vec<usize> = index.into_values().collect();
+        unknown.sort_unstable();
+        order.extend(unknown);
+        Ok(Some(order))
+    }
+    /// Evaluates the target of a `Spread` key to the entries it contributes to the outer map.
+    pub(crate) fn spread(
+        &mut self,
+        target: BuilderDataType<'de>,
+    ) -> Result<Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>, BuilderError> {
+        match self.evaluate(target)? {
+            BuilderDataType::Empty => Ok(Vec::new()),
+            BuilderDataType::Map(v) => Ok(v),
+            b => Err(BuilderError::TypeMismatch {
+                expected: "Map",
+                found: b.kind(),
+            }),
+        }
+    }
+    /// Number of elements a list yields, leaving out `Empty` when it is skipped, or `None` when
+    /// a `Spread` makes it unknown until the list is read.
+    pub(crate) fn list_len(&self, v: &[BuilderDataType<'de>]) -> Option<usize> {
+        if v.iter().any(|e| matches!(e, BuilderDataType::Spread(_))) {
+            return None;
+        }
+        if self.skip_empty {
+            return Some(
+                v.iter()
+                    .filter(|e| !matches!(e, BuilderDataType::Empty))
+                    .count(),
+            );
+        }
+        Some(v.len())
+    }
+    /// Number of entries a map yields, or `None` when a `Spread` key or a duplicate key policy
+    /// that skips entries makes it unknown until the map is read.
+    pub(crate) fn map_len(
+        &self,
+        v: &[(BuilderDataType<'de>, BuilderDataType<'de>)],
+    ) -> Option<usize> {
+        if !self.duplicate_keys.yields_all()
+            || v.iter()
+                .any(|(k, _)| matches!(k, BuilderDataType::Spread(_)))
+        {
+            return None;
+        }
+        Some(v.len())
+    }
+    pub(crate) fn resolve_list(
+        &mut self,
+        b: BuilderDataType<'de>,
+    ) -> Result<Vec<BuilderDataType<'de>>, BuilderError> {
+        match self.evaluate(b)? {
+            BuilderDataType::Empty => Ok(Vec::new()),
+            BuilderDataType::List(v) => Ok(v),
+            b => Err(BuilderError::TypeMismatch {
+                expected: "List",
+                found: b.kind(),
+            }),
+        }
+    }
+    /// Folds the evaluated operands with the operation matching the widest operand type:
+    /// `Number` or `Float32` over `Integer` over `Unsigned`.
+    fn arithmetic(
+        &mut self,
+        op: &'static str,
+        v: Vec<BuilderDataType<'de>>,
+        float: fn(f64, f64) -> Result<f64, BuilderError>,
+        signed: fn(i64, i64) -> Result<i64, BuilderError>,
+        unsigned: fn(u64, u64) -> Result<u64, BuilderError>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        operand_count(op, v.len(), 1, usize::MAX)?;
+        let v = v
+            .into_iter()
+            .enumerate()
+            .map(|(index, b)| {
+                self.evaluate(b).map_err(|source| BuilderError::Context {
+                    op,
+                    index,
+                    source: Box::new(source),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (first, rest) = (&v[0], &v[1..]);
+        Ok(
+            if v.iter()
+                .any(|b| matches!(b, BuilderDataType::Number(_) | BuilderDataType::Float32(_)))
+            {
+                BuilderDataType::Number(
+                    rest.iter()
+                        .try_fold(first.to_float(), |a, b| float(a, b.to_float()))?,
+                )
+            } else if v.iter().any(|b| matches!(b, BuilderDataType::Integer(_))) {
+                BuilderDataType::Integer(
+                    rest.iter()
+                        .try_fold(first.to_signed(), |a, b| signed(a, b.to_signed()))?,
+                )
+            } else {
+                BuilderDataType::Unsigned(
+                    rest.iter()
+                        .try_fold(first.to_unsigned(), |a, b| unsigned(a, b.to_unsigned()))?,
+                )
+            },
+        )
+    }
+    pub(crate) fn sum(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        self.arithmetic(
+            "Sum",
+            v,
+            |a, b| Ok(a + b),
+            |a, b| Ok(a.saturating_add(b)),
+            |a, b| Ok(a.saturating_add(b)),
+        )
+    }
+    pub(crate) fn multiply(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        self.arithmetic(
+            "Multiply",
+            v,
+            |a, b| Ok(a * b),
+            |a, b| Ok(a.saturating_mul(b)),
+            |a, b| Ok(a.saturating_mul(b)),
+        )
+    }
+    pub(crate) fn divide(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        self.arithmetic(
+            "Divide",
+            v,
+            |a, b| match b {
+                0.0 => Err(BuilderError::DivisionByZero),
+                b => Ok(a / b),
+            },
+            |a, b| match b {
+                0 => Err(BuilderError::DivisionByZero),
+                b => Ok(a.saturating_div(b)),
+            },
+            |a, b| a.checked_div(b).ok_or(BuilderError::DivisionByZero),
+        )
+    }
+    /// Parses the `[string, radix?]` operands of a `ParseInt`. Radix must be within 2..=36.
+    pub(crate) fn parse_int(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        operand_count("ParseInt", v.len(), 1, 2)?;
+        let mut i = v.into_iter();
+        let input = i.next().unwrap_or(BuilderDataType::Empty);
+        let radix = match i.next() {
+            Some(radix) => self.evaluate(radix)?.to_unsigned(),
+            None => 10,
         };
-        let Some(if_false) = i.next() else {
+        if !(2..=36).contains(&radix) {
             return Err(BuilderError::InvalidFunctionArgument);
+        }
+        let input = self.evaluate(input)?.to_string();
+        i64::from_str_radix(input.trim(), radix as u32)
+            .map(BuilderDataType::Integer)
+            .map_err(|_| BuilderError::ParseFailed(input.into_owned()))
+    }
+    /// Parses the `[string]` operand of a `ParseFloat`.
+    pub(crate) fn parse_float(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [input] = into_operands("ParseFloat", v)?;
+        let input = self.evaluate(input)?.to_string();
+        input
+            .trim()
+            .parse::<f64>()
+            .map(BuilderDataType::Number)
+            .map_err(|_| BuilderError::ParseFailed(input.into_owned()))
+    }
+    /// Evaluates the `[label, value]` operands of a `Tap` and passes their string forms to the
+    /// tap callback, if one is set.
+    pub(crate) fn tap(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [label, value] = into_operands("Tap", v)?;
+        let label = self.evaluate(label)?;
+        let value = self.evaluate(value)?;
+        if let Some(tap) = &self.tap {
+            tap(&label.to_string(), &value.to_string());
+        }
+        Ok(value)
+    }
+    /// Escapes the string form of the `[value, style]` operands of a `Quote`.
+    pub(crate) fn quote(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [value, style] = into_operands("Quote", v)?;
+        let value = self.evaluate(value)?;
+        let value = value.to_string();
+        let quoted = match self.evaluate(style)?.to_string().as_ref() {
+            "json" => quote_json(&value),
+            "csv" => quote_csv(&value),
+            "shell" => quote_shell(&value),
+            _ => return Err(BuilderError::InvalidFunctionArgument),
         };
-        if self.resolve(condition)?.check_true() {
-            Ok(if_true)
-        } else {
-            Ok(if_false)
+        Ok(BuilderDataType::String(Cow::Owned(quoted)))
+    }
+    /// Replaces `from` with `to` in the string form of the `[string, from, to]` operands of a
+    /// `Replace`.
+    pub(crate) fn replace(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [input, from, to] = into_operands("Replace", v)?;
+        let input = self.evaluate(input)?.to_string();
+        let from = self.evaluate(from)?.to_string();
+        let to = self.evaluate(to)?.to_string();
+        if from.is_empty() {
+            return Ok(BuilderDataType::String(input));
         }
+        Ok(BuilderDataType::String(Cow::Owned(
+            input.replace(from.as_ref(), &to),
+        )))
     }
+    fn rng(&mut self) -> RefMut<'_, Rng> {
+        self.rng.borrow_mut()
+    }
+    /// Reorders the list of a `Shuffle` with Fisher-Yates, drawing from the seeded generator.
+    pub(crate) fn shuffle(
+        &mut self,
+        list: BuilderDataType<'de>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let mut v = self.resolve_list(list)?;
+        let mut rng = self.rng();
+        for i in (1..v.len()).rev() {
+            v.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        Ok(BuilderDataType::List(v))
+    }
+    /// Walks the `[root, segment...]` operands of a `Path`, returning `Empty` on the first
+    /// segment that does not match.
+    pub(crate) fn path(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        operand_count("Path", v.len(), 1, usize::MAX)?;
+        let mut i = v.into_iter();
+        let mut node = self.evaluate(i.next().unwrap_or(BuilderDataType::Empty))?;
+        for segment in i {
+            let segment = self.evaluate(segment)?;
+            let next = match (node, segment) {
+                (BuilderDataType::List(mut v), BuilderDataType::Integer(n)) if n >= 0 => {
+                    ((n as usize) < v.len()).then(|| v.swap_remove(n as usize))
+                }
+                (BuilderDataType::List(mut v), BuilderDataType::Unsigned(n)) => {
+                    ((n as usize) < v.len()).then(|| v.swap_remove(n as usize))
+                }
+                (BuilderDataType::Map(v), BuilderDataType::String(name)) => {
+                    let mut found = None;
+                    for (key, value) in v {
+                        if self.evaluate(key)?.to_string() == name {
+                            found = Some(value);
+                            break;
+                        }
+                    }
+                    found
+                }
+                _ => None,
+            };
+            let Some(next) = next else {
+                return Ok(BuilderDataType::Empty);
+            };
+            node = self.evaluate(next)?;
+        }
+        Ok(node)
+    }
+    /// Evaluates the `primary` operand of a `[primary, fallback]` pair, returning `fallback` if
+    /// that fails. Only errors raised while evaluating `primary` to a value are caught.
+    pub(crate) fn attempt(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [primary, fallback] = into_operands("Try", v)?;
+        match self.evaluate(primary) {
+            Ok(data) => Ok(data),
+            Err(_) => Ok(fallback),
+        }
+    }
+    /// Splits the list in the `[list, predicate]` operands of a `Partition` into a `List` of the
+    /// elements the predicate holds for and a `List` of the rest.
+    pub(crate) fn partition(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [list, predicate] = into_operands("Partition", v)?;
+        let (mut matching, mut rest) = (Vec::new(), Vec::new());
+        for element in self.resolve_list(list)? {
+            if self
+                .nested(vec![element.clone()])
+                .resolve_to_bool(&predicate)?
+            {
+                matching.push(element);
+            } else {
+                rest.push(element);
+            }
+        }
+        Ok(BuilderDataType::List(vec![
+            BuilderDataType::List(matching),
+            BuilderDataType::List(rest),
+        ]))
+    }
+    /// Evaluates the `[list, init, combine]` operands of a `Scan`, returning every intermediate
+    /// accumulator. `combine` sees the accumulator as `Argument(0)` and the element as
+    /// `Argument(1)`.
+    pub(crate) fn scan(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<Vec<BuilderDataType<'de>>, BuilderError> {
+        let [list, init, combine] = into_operands("Scan", v)?;
+        let list = self.resolve_list(list)?;
+        let mut acc = self.evaluate(init)?;
+        let mut result = Vec::with_capacity(list.len());
+        for element in list {
+            let mut closure = self.nested(vec![acc, element]);
+            acc = closure.evaluate(combine.clone())?;
+            result.push(acc.clone());
+        }
+        Ok(result)
+    }
+    /// Evaluates the `[start, end]` operands of a `Range` to the integers between them. A range
+    /// longer than the iteration limit fails instead of being built.
+    pub(crate) fn range(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<Vec<BuilderDataType<'de>>, BuilderError> {
+        let [start, end] = into_operands("Range", v)?;
+        let start = self.evaluate(start)?.to_signed();
+        let end = self.evaluate(end)?.to_signed();
+        if end.saturating_sub(start).max(0) as u64 > self.iteration_limit as u64 {
+            return Err(BuilderError::IterationLimit(self.iteration_limit));
+        }
+        Ok((start..end).map(BuilderDataType::Integer).collect())
+    }
+    pub(crate) fn pad(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<Vec<BuilderDataType<'de>>, BuilderError> {
+        let [list, length, fill] = into_operands("Pad", v)?;
+        let mut list = self.resolve_list(list)?;
+        let length = self.evaluate(length)?.to_unsigned() as usize;
+        list.resize(length, fill);
+        Ok(list)
+    }
+    /// Binds the `[name, value, body]` operands of a `Let` and returns its body.
+    ///
+    /// The value is evaluated once, so a `Take` is drained a single time however often the body
+    /// reads the binding.
+    ///
+    /// The caller is responsible for popping the binding once the body is deserialized.
+    pub(crate) fn bind(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [name, value, body] = into_operands("Let", v)?;
+        let name = self.resolve(name)?.to_string();
+        let value = match self.resolve(value)? {
+            BuilderDataType::Take(r) => take_cell(&r)?,
+            value => value,
+        };
+        self.bindings.push((name, value));
+        Ok(body)
+    }
+    pub(crate) fn bind_ref<'a>(
+        &mut self,
+        v: &'a [BuilderDataType<'de>],
+    ) -> Result<&'a BuilderDataType<'de>, BuilderError> {
+        let [name, value, body] = operands("Let", v)?;
+        let name = self.resolve_clone(name)?.to_string();
+        let value = match self.resolve_clone(value)? {
+            BuilderDataType::Take(r) => take_cell(&r)?,
+            value => value,
+        };
+        self.bindings.push((name, value));
+        Ok(body)
+    }
+    pub(crate) fn var(
+        &mut self,
+        name: &BuilderDataType<'de>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let name = self.resolve_clone(name)?.to_string();
+        match self.bindings.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, value)) => Ok(value.clone()),
+            None => Err(BuilderError::UnboundVariable(name.into_owned())),
+        }
+    }
+    /// Defines the function in the `[name, function, body]` operands of a `DefineFn` and returns
+    /// its body.
+    ///
+    /// The caller is responsible for popping the function once the body is deserialized.
+    pub(crate) fn define(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let [name, function, body] = into_operands("DefineFn", v)?;
+        let name = self.resolve(name)?.to_string();
+        self.functions.push((name, Rc::new(function)));
+        Ok(body)
+    }
+    pub(crate) fn define_ref<'a>(
+        &mut self,
+        v: &'a [BuilderDataType<'de>],
+    ) -> Result<&'a BuilderDataType<'de>, BuilderError> {
+        let [name, function, body] = operands("DefineFn", v)?;
+        let name = self.resolve_clone(name)?.to_string();
+        self.functions.push((name, Rc::new(function.clone())));
+        Ok(body)
+    }
+    /// Looks up the function named by the first operand of a `CallFn` and resolves the rest
+    /// as its arguments.
+    pub(crate) fn call(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<(Rc<BuilderDataType<'de>>, Vec<BuilderDataType<'de>>), BuilderError> {
+        let mut v = v.into_iter();
+        let function = self.function(v.next())?;
+        let args = v.map(|a| self.resolve(a)).collect::<Result<_, _>>()?;
+        Ok((function, args))
+    }
+    pub(crate) fn call_ref(
+        &mut self,
+        v: &[BuilderDataType<'de>],
+    ) -> Result<(Rc<BuilderDataType<'de>>, Vec<BuilderDataType<'de>>), BuilderError> {
+        let mut v = v.iter();
+        let function = self.function(v.next().cloned())?;
+        let args = v.map(|a| self.resolve_clone(a)).collect::<Result<_, _>>()?;
+        Ok((function, args))
+    }
+    fn function(
+        &mut self,
+        name: Option<BuilderDataType<'de>>,
+    ) -> Result<Rc<BuilderDataType<'de>>, BuilderError> {
+        let Some(name) = name else {
+            return Err(BuilderError::WrongArity {
+                op: "CallFn",
+                expected: 1,
+                found: 0,
+            });
+        };
+        let name = self.resolve(name)?.to_string();
+        match self.functions.iter().rev().find(|(n, _)| *n == name) {
+            Some((_, function)) => Ok(function.clone()),
+            None => Err(BuilderError::UndefinedFunction(name.into_owned())),
+        }
+    }
+    /// Looks up the environment variable named by `name`, yielding `Empty` when it is unset.
+    #[cfg(feature = "std-env")]
+    pub(crate) fn env(
+        &mut self,
+        name: &BuilderDataType<'de>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let name = self.resolve_clone(name)?.to_string();
+        let value = match &self.env {
+            Some(env) => env.get(name.as_ref()).cloned(),
+            None => std::env::var(name.as_ref()).ok(),
+        };
+        Ok(value.map_or(BuilderDataType::Empty, |v| {
+            BuilderDataType::String(Cow::Owned(v))
+        }))
+    }
+    /// Builds the `secs`/`nanos` map of a `Duration` from `[secs, nanos]`, or from `[secs]`
+    /// where a fractional part becomes the nanoseconds.
+    pub(crate) fn duration(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        operand_count("Duration", v.len(), 1, 2)?;
+        let mut i = v.into_iter();
+        let secs = self.evaluate(i.next().unwrap_or(BuilderDataType::Empty))?;
+        let (secs, nanos) = match (i.next(), secs) {
+            (Some(nanos), secs) => (secs.to_unsigned(), self.evaluate(nanos)?.to_unsigned()),
+            (None, secs @ (BuilderDataType::Number(_) | BuilderDataType::Float32(_))) => {
+                let duration = core::time::Duration::try_from_secs_f64(secs.to_float())
+                    .map_err(|_| BuilderError::InvalidFunctionArgument)?;
+                (duration.as_secs(), u64::from(duration.subsec_nanos()))
+            }
+            (None, secs) => (secs.to_unsigned(), 0),
+        };
+        Ok(BuilderDataType::Map(vec![
+            (
+                BuilderDataType::String(Cow::Borrowed("secs")),
+                BuilderDataType::Unsigned(secs),
+            ),
+            (
+                BuilderDataType::String(Cow::Borrowed("nanos")),
+                BuilderDataType::Unsigned(nanos),
+            ),
+        ]))
+    }
+    /// Evaluates the `[string, pattern]` operands shared by `Matches` and `Capture`.
+    #[cfg(feature = "regex")]
+    fn regex_operands(
+        &mut self,
+        op: &'static str,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<(String, regex::Regex), BuilderError> {
+        let [input, pattern] = into_operands(op, v)?;
+        let input = self.evaluate(input)?.to_string().into_owned();
+        let pattern = self.evaluate(pattern)?.to_string();
+        let regex = regex::Regex::new(&pattern)
+            .map_err(|_| BuilderError::InvalidPattern(pattern.into_owned()))?;
+        Ok((input, regex))
+    }
+    #[cfg(feature = "regex")]
+    pub(crate) fn matches(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let (input, regex) = self.regex_operands("Matches", v)?;
+        Ok(BuilderDataType::Boolean(regex.is_match(&input)))
+    }
+    #[cfg(feature = "regex")]
+    pub(crate) fn capture_group(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        let (input, regex) = self.regex_operands("Capture", v)?;
+        Ok(regex
+            .captures(&input)
+            .and_then(|c| c.get(1))
+            .map_or(BuilderDataType::Empty, |m| {
+                BuilderDataType::String(Cow::Owned(String::from(m.as_str())))
+            }))
+    }
+    /// Formats the `[secs, format]` operands of a `FormatDate`. A timestamp out of chrono's range
+    /// is an invalid argument.
+    #[cfg(feature = "chrono")]
+    pub(crate) fn format_date(
+        &mut self,
+        v: Vec<BuilderDataType<'de>>,
+    ) -> Result<BuilderDataType<'de>, BuilderError> {
+        use core::fmt::Write;
+        let [secs, format] = into_operands("FormatDate", v)?;
+        let secs = self.evaluate(secs)?.to_signed();
+        let format = self.evaluate(format)?.to_string();
+        let date = chrono::DateTime::from_timestamp(secs, 0)
+            .ok_or(BuilderError::InvalidFunctionArgument)?;
+        let mut out = String::new();
+        write!(out, "{}", date.format(&format))
+            .map_err(|_| BuilderError::InvalidDateFormat(format.into_owned()))?;
+        Ok(BuilderDataType::String(Cow::Owned(out)))
+    }
+}
+
+/// Wraps `value` in double quotes with JSON string escapes.
+fn quote_json(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break, doubling quotes.
+fn quote_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Wraps `value` in single quotes for a POSIX shell, closing and escaping embedded quotes.
+fn quote_shell(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
 }
@@ -0,0 +1,224 @@
+use crate::closure::{TapFn, DEFAULT_ITERATION_LIMIT, DEFAULT_RECURSION_LIMIT};
+use crate::rng::Rng;
+use crate::{
+    BuilderDataType, BuilderDeserializer, BuilderDeserializerRef, BuilderError, Closure,
+    DuplicateKeyPolicy, Stats,
+};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use serde::Deserialize;
+#[cfg(feature = "std-env")]
+use std::collections::HashMap;
+
+/// Deserialization options, combined with builder methods instead of one entry point per
+/// combination.
+///
+/// Every setting of a `Closure` has a setter here. Its bindings, functions and depth are not
+/// settings: they are built up while evaluating and always start empty.
+#[derive(Clone)]
+pub struct DeserializerConfig<'de> {
+    strict: bool,
+    sort_maps: bool,
+    group_keys: bool,
+    skip_empty: bool,
+    check_arity: bool,
+    iteration_limit: usize,
+    recursion_limit: usize,
+    duplicate_keys: DuplicateKeyPolicy,
+    args: Vec<BuilderDataType<'de>>,
+    seed: u64,
+    tap: Option<Rc<TapFn>>,
+    stats: Option<Rc<RefCell<Stats>>>,
+    #[cfg(feature = "std-time")]
+    clock: Option<fn() -> u64>,
+    #[cfg(feature = "std-env")]
+    env: Option<Rc<HashMap<String, String>>>,
+}
+
+impl<'de> core::fmt::Debug for DeserializerConfig<'de> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("DeserializerConfig");
+        s.field("strict", &self.strict)
+            .field("sort_maps", &self.sort_maps)
+            .field("group_keys", &self.group_keys)
+            .field("skip_empty", &self.skip_empty)
+            .field("check_arity", &self.check_arity)
+            .field("iteration_limit", &self.iteration_limit)
+            .field("recursion_limit", &self.recursion_limit)
+            .field("duplicate_keys", &self.duplicate_keys)
+            .field("args", &self.args)
+            .field("seed", &self.seed)
+            .field("tap", &self.tap.is_some())
+            .field("stats", &self.stats);
+        #[cfg(feature = "std-time")]
+        s.field("clock", &self.clock);
+        #[cfg(feature = "std-env")]
+        s.field("env", &self.env);
+        s.finish()
+    }
+}
+
+impl<'de> Default for DeserializerConfig<'de> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'de> DeserializerConfig<'de> {
+    pub fn new() -> Self {
+        DeserializerConfig {
+            strict: false,
+            sort_maps: false,
+            group_keys: false,
+            skip_empty: false,
+            check_arity: false,
+            iteration_limit: DEFAULT_ITERATION_LIMIT,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            duplicate_keys: DuplicateKeyPolicy::Last,
+            args: Vec::new(),
+            seed: 0,
+            tap: None,
+            stats: None,
+            #[cfg(feature = "std-time")]
+            clock: None,
+            #[cfg(feature = "std-env")]
+            env: None,
+        }
+    }
+
+    /// Disables the loose coercions into booleans, integers and strings.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Serves map entries sorted by the string form of their keys.
+    pub fn sort_maps(mut self, sort_maps: bool) -> Self {
+        self.sort_maps = sort_maps;
+        self
+    }
+
+    /// Gathers the values of consecutive entries with equal keys into a `List`.
+    pub fn group_keys(mut self, group_keys: bool) -> Self {
+        self.group_keys = group_keys;
+        self
+    }
+
+    /// Leaves `Empty` elements of a `List` out of the sequence instead of deserializing them.
+    pub fn skip_empty(mut self, skip_empty: bool) -> Self {
+        self.skip_empty = skip_empty;
+        self
+    }
+
+    /// Scans the body of each `Closure` and `Apply` for the arguments it reads before evaluating
+    /// it, failing with `ArgCountMismatch` when too few are given.
+    pub fn check_arity(mut self, check_arity: bool) -> Self {
+        self.check_arity = check_arity;
+        self
+    }
+
+    /// Caps the number of elements a `While` loop may produce.
+    pub fn iteration_limit(mut self, limit: usize) -> Self {
+        self.iteration_limit = limit;
+        self
+    }
+
+    /// Caps how deeply nodes may nest, failing with `RecursionLimit` beyond it. Defaults to 64.
+    pub fn recursion_limit(mut self, limit: usize) -> Self {
+        self.recursion_limit = limit;
+        self
+    }
+
+    pub fn duplicate_keys(mut self, policy: DuplicateKeyPolicy) -> Self {
+        self.duplicate_keys = policy;
+        self
+    }
+
+    /// Top-level arguments, read with `Argument(0)` onwards.
+    pub fn args(mut self, args: Vec<BuilderDataType<'de>>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Seeds the generator behind `Shuffle` and `Weighted`; the same seed makes the same choices.
+    /// Defaults to 0.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Receives the label and value of each evaluated `Tap`.
+    pub fn tap(mut self, tap: impl Fn(&str, &str) + 'static) -> Self {
+        self.tap = Some(Rc::new(tap));
+        self
+    }
+
+    /// Counts the work done into `stats`, which the caller keeps to read afterwards.
+    pub fn stats(mut self, stats: Rc<RefCell<Stats>>) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Replaces the system clock read by `Now`.
+    #[cfg(feature = "std-time")]
+    pub fn clock(mut self, clock: fn() -> u64) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Variables read by `Env`, instead of the process environment.
+    #[cfg(feature = "std-env")]
+    pub fn env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = Some(Rc::new(env));
+        self
+    }
+
+    /// Creates a top-level closure with these options.
+    pub fn closure(&self) -> Closure<'de> {
+        let mut closure = Closure::new();
+        closure.strict = self.strict;
+        closure.sort_maps = self.sort_maps;
+        closure.group_keys = self.group_keys;
+        closure.skip_empty = self.skip_empty;
+        closure.check_arity = self.check_arity;
+        closure.iteration_limit = self.iteration_limit;
+        closure.recursion_limit = self.recursion_limit;
+        closure.duplicate_keys = self.duplicate_keys;
+        closure.args = self.args.iter().cloned().map(Rc::new).collect();
+        closure.rng = Rc::new(RefCell::new(Rng::new(self.seed)));
+        closure.tap = self.tap.clone();
+        closure.stats = self.stats.clone();
+        #[cfg(feature = "std-time")]
+        if let Some(clock) = self.clock {
+            closure.clock = clock;
+        }
+        #[cfg(feature = "std-env")]
+        {
+            closure.env = self.env.clone();
+        }
+        closure
+    }
+
+    pub fn deserialize<T>(&self, data: BuilderDataType<'de>) -> Result<T, BuilderError>
+    where
+        T: Deserialize<'de>,
+    {
+        let mut closure = self.closure();
+        T::deserialize(BuilderDeserializer {
+            closure: &mut closure,
+            data,
+        })
+    }
+
+    pub fn deserialize_ref<T>(&self, data: &BuilderDataType<'de>) -> Result<T, BuilderError>
+    where
+        T: Deserialize<'de>,
+    {
+        let mut closure = self.closure();
+        T::deserialize(BuilderDeserializerRef {
+            closure: &mut closure,
+            data,
+        })
+    }
+}
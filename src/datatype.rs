@@ -1,37 +1,410 @@
-use std::borrow::Cow;
-use std::cell::RefCell;
-use std::rc::{Rc, Weak};
-
-use serde::Deserialize;
+use crate::closure::{read_cell, SIZE_HINT_LIMIT};
+use crate::errors::BuilderError;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::marker::PhantomData;
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum BuilderDataType<'de> {
     Empty,
+    /// A present but null value. Unlike `Empty`, which is visited as a missing option, `Null`
+    /// is visited as a unit, which `Option` also reads as `None`.
+    Null,
     Boolean(bool),
     Integer(i64),
     Unsigned(u64),
     Number(f64),
+    /// A single precision number, visited as `f32` so it reaches `f32` fields without rounding.
+    Float32(f32),
     String(Cow<'de, str>),
+    /// Raw bytes. Converts to a string as lossy UTF-8 and to a number as its length.
+    Bytes(Cow<'de, [u8]>),
     Map(Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>),
     List(Vec<BuilderDataType<'de>>),
+    /// Deserializes the first element with every element, itself included, as arguments, so
+    /// `Argument(0)` refers to the body and the first real argument is `Argument(1)`.
     Closure(Vec<BuilderDataType<'de>>),
+    /// Deserializes the body with the given arguments, the first of which is `Argument(0)`.
+    Apply(Box<BuilderDataType<'de>>, Vec<BuilderDataType<'de>>),
     Argument(usize),
+    /// Like `Argument`, but evaluates to the boxed default when the argument is not supplied.
+    ArgumentOr(usize, Box<BuilderDataType<'de>>),
     TakeFromArgument(usize),
     PopArgument,
     Reference(Rc<BuilderDataType<'de>>),
     SelfReference(Weak<BuilderDataType<'de>>),
+    /// Like `SelfReference`, but deserializes the boxed default once the target is dropped.
+    SelfReferenceOr(Weak<BuilderDataType<'de>>, Box<BuilderDataType<'de>>),
+    /// Reads the shared cell without changing it. The owned deserializer moves the contents out
+    /// when it holds the last handle and clones them otherwise, so `from_data` and `from_ref`
+    /// yield the same values.
     Store(Rc<RefCell<BuilderDataType<'de>>>),
+    /// Takes one value out of the shared cell each time it is deserialized, as `take_one`
+    /// describes. Once the cell is exhausted it yields `Empty`, which `Option` reads as `None`.
     Take(Rc<RefCell<BuilderDataType<'de>>>),
+    /// Yields the shared flag as a `Boolean` and flips it, so successive reads alternate. Unlike
+    /// `Take` of a `Boolean`, which is true once and false from then on.
+    Toggle(Rc<RefCell<bool>>),
     IfThenElse(Vec<BuilderDataType<'de>>),
     Repeat(Vec<BuilderDataType<'de>>),
+    /// `[count, key, value]`: a map of `count` entries, with `Index` counting entries.
+    RepeatMap(Vec<BuilderDataType<'de>>),
+    /// `[seed, count, step]`: a list of `count` elements starting at `seed`, where `step`
+    /// computes each next element from the previous one, passed as `Argument(0)`.
+    Unfold(Vec<BuilderDataType<'de>>),
+    /// Takes one element from each list in turn, continuing with the longer lists once the
+    /// shorter ones run out.
+    Interleave(Vec<BuilderDataType<'de>>),
+    /// `[start, end]`: the integers from `start` up to but not including `end`.
     Range(Vec<BuilderDataType<'de>>),
     Sum(Vec<BuilderDataType<'de>>),
     Multiply(Vec<BuilderDataType<'de>>),
+    /// Position in the nearest enclosing sequence, or 0 outside of any sequence.
     Index,
+    /// Number of arguments in scope, for checking how closures compose.
+    ArgCount,
     Unique,
+    Pad(Vec<BuilderDataType<'de>>),
+    Let(Vec<BuilderDataType<'de>>),
+    Var(Box<BuilderDataType<'de>>),
+    /// `[name, function, body]`: deserializes the body with `function` callable by name through
+    /// `CallFn`, including from within the function itself.
+    DefineFn(Vec<BuilderDataType<'de>>),
+    /// `[name, argument...]`: deserializes the function defined under the name with the given
+    /// arguments, the first of which is `Argument(0)`.
+    CallFn(Vec<BuilderDataType<'de>>),
+    Switch(Vec<BuilderDataType<'de>>),
+    /// `[weight, value, ...]`: picks one value with probability proportional to its weight,
+    /// drawing from the seeded generator.
+    Weighted(Vec<BuilderDataType<'de>>),
+    While(Vec<BuilderDataType<'de>>),
+    Scan(Vec<BuilderDataType<'de>>),
+    /// `[list, predicate]`: splits the list into `[matching, rest]`, where the predicate sees each
+    /// element as `Argument(0)`.
+    Partition(Vec<BuilderDataType<'de>>),
+    Assert(Vec<BuilderDataType<'de>>),
+    Divide(Vec<BuilderDataType<'de>>),
+    Try(Vec<BuilderDataType<'de>>),
+    /// In the key position of a `Map` entry, inlines the entries of the target map. The value
+    /// of that entry is ignored. As an element of a `List`, inlines the elements of the target
+    /// list.
+    Spread(Box<BuilderDataType<'de>>),
+    /// Parses `[string, radix?]` as an `Integer`, with radix 10 by default.
+    ParseInt(Vec<BuilderDataType<'de>>),
+    /// Parses `[string]` as a `Number`.
+    ParseFloat(Vec<BuilderDataType<'de>>),
+    /// Evaluates `[label, value]`, reports both to the tap callback and yields the value.
+    Tap(Vec<BuilderDataType<'de>>),
+    /// Walks `[root, segment...]`, where a string segment selects a map entry by key and an
+    /// integer segment a list element. Yields `Empty` when a segment does not match.
+    Path(Vec<BuilderDataType<'de>>),
+    /// Escapes the string form of `[value, style]` for embedding, with style `"json"`, `"csv"`
+    /// or `"shell"`.
+    Quote(Vec<BuilderDataType<'de>>),
+    /// Replaces every occurrence of `from` in the string form of `[string, from, to]` with `to`.
+    /// An empty `from` leaves the string unchanged.
+    Replace(Vec<BuilderDataType<'de>>),
+    /// `[tag, payload]`: an externally tagged enum variant, deserialized as the single-entry
+    /// map `{tag: payload}`.
+    Variant(Vec<BuilderDataType<'de>>),
+    /// Captures the arguments in scope where it is resolved, for example when passed as an
+    /// argument, so the expression later evaluates against them rather than the use site's.
+    Lazy(Box<BuilderDataType<'de>>),
+    /// Deserializes as the boxed node; the annotation only shows up in `pretty`.
+    Comment(Box<BuilderDataType<'de>>, Rc<str>),
+    /// Yields the elements of the boxed list in an order drawn from the seeded generator.
+    Shuffle(Box<BuilderDataType<'de>>),
+    #[cfg(feature = "std-time")]
+    Now,
+    /// `[secs, nanos]`, or `[secs]` with fractional seconds, presented as the `secs`/`nanos`
+    /// struct serde uses for `std::time::Duration`.
+    Duration(Vec<BuilderDataType<'de>>),
+    #[cfg(feature = "std-env")]
+    Env(Box<BuilderDataType<'de>>),
+    /// Tests the string form of `[string, pattern]` against a regular expression, yielding a
+    /// `Boolean`.
+    #[cfg(feature = "regex")]
+    Matches(Vec<BuilderDataType<'de>>),
+    /// Yields the first group of the regular expression in `[string, pattern]` as a `String`,
+    /// or `Empty` when it does not match.
+    #[cfg(feature = "regex")]
+    Capture(Vec<BuilderDataType<'de>>),
+    /// Formats the Unix timestamp in `[secs, format]` with a `strftime` format string, yielding
+    /// a `String` in UTC.
+    #[cfg(feature = "chrono")]
+    FormatDate(Vec<BuilderDataType<'de>>),
 }
 
 impl<'de> BuilderDataType<'de> {
+    /// Name of the variant, used in error messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BuilderDataType::Empty => "Empty",
+            BuilderDataType::Null => "Null",
+            BuilderDataType::Boolean(_) => "Boolean",
+            BuilderDataType::Integer(_) => "Integer",
+            BuilderDataType::Unsigned(_) => "Unsigned",
+            BuilderDataType::Number(_) => "Number",
+            BuilderDataType::Float32(_) => "Float32",
+            BuilderDataType::String(_) => "String",
+            BuilderDataType::Bytes(_) => "Bytes",
+            BuilderDataType::Map(_) => "Map",
+            BuilderDataType::List(_) => "List",
+            BuilderDataType::Closure(_) => "Closure",
+            BuilderDataType::Apply(..) => "Apply",
+            BuilderDataType::Argument(_) => "Argument",
+            BuilderDataType::ArgumentOr(..) => "ArgumentOr",
+            BuilderDataType::TakeFromArgument(_) => "TakeFromArgument",
+            BuilderDataType::PopArgument => "PopArgument",
+            BuilderDataType::Reference(_) => "Reference",
+            BuilderDataType::SelfReference(_) => "SelfReference",
+            BuilderDataType::SelfReferenceOr(..) => "SelfReferenceOr",
+            BuilderDataType::Store(_) => "Store",
+            BuilderDataType::Take(_) => "Take",
+            BuilderDataType::Toggle(_) => "Toggle",
+            BuilderDataType::IfThenElse(_) => "IfThenElse",
+            BuilderDataType::Repeat(_) => "Repeat",
+            BuilderDataType::RepeatMap(_) => "RepeatMap",
+            BuilderDataType::Unfold(_) => "Unfold",
+            BuilderDataType::Interleave(_) => "Interleave",
+            BuilderDataType::Range(_) => "Range",
+            BuilderDataType::Sum(_) => "Sum",
+            BuilderDataType::Multiply(_) => "Multiply",
+            BuilderDataType::Index => "Index",
+            BuilderDataType::ArgCount => "ArgCount",
+            BuilderDataType::Unique => "Unique",
+            BuilderDataType::Pad(_) => "Pad",
+            BuilderDataType::Let(_) => "Let",
+            BuilderDataType::Var(_) => "Var",
+            BuilderDataType::DefineFn(_) => "DefineFn",
+            BuilderDataType::CallFn(_) => "CallFn",
+            BuilderDataType::Switch(_) => "Switch",
+            BuilderDataType::Weighted(_) => "Weighted",
+            BuilderDataType::While(_) => "While",
+            BuilderDataType::Scan(_) => "Scan",
+            BuilderDataType::Partition(_) => "Partition",
+            BuilderDataType::Assert(_) => "Assert",
+            BuilderDataType::Divide(_) => "Divide",
+            BuilderDataType::Try(_) => "Try",
+            BuilderDataType::Spread(_) => "Spread",
+            BuilderDataType::Lazy(_) => "Lazy",
+            BuilderDataType::Comment(..) => "Comment",
+            BuilderDataType::Shuffle(_) => "Shuffle",
+            BuilderDataType::ParseInt(_) => "ParseInt",
+            BuilderDataType::ParseFloat(_) => "ParseFloat",
+            BuilderDataType::Tap(_) => "Tap",
+            BuilderDataType::Quote(_) => "Quote",
+            BuilderDataType::Replace(_) => "Replace",
+            BuilderDataType::Variant(_) => "Variant",
+            BuilderDataType::Path(_) => "Path",
+            #[cfg(feature = "std-time")]
+            BuilderDataType::Now => "Now",
+            BuilderDataType::Duration(_) => "Duration",
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(_) => "Env",
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(_) => "Matches",
+            #[cfg(feature = "regex")]
+            BuilderDataType::Capture(_) => "Capture",
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(_) => "FormatDate",
+        }
+    }
+
+    /// Returns true for variants that `Closure::resolve` replaces with another value.
+    pub(crate) fn is_resolvable(&self) -> bool {
+        match self {
+            BuilderDataType::Argument(_)
+            | BuilderDataType::ArgumentOr(..)
+            | BuilderDataType::TakeFromArgument(_)
+            | BuilderDataType::IfThenElse(_)
+            | BuilderDataType::Pad(_)
+            | BuilderDataType::Var(_)
+            | BuilderDataType::Switch(_)
+            | BuilderDataType::Weighted(_)
+            | BuilderDataType::Sum(_)
+            | BuilderDataType::Multiply(_)
+            | BuilderDataType::Range(_)
+            | BuilderDataType::Scan(_)
+            | BuilderDataType::Partition(_)
+            | BuilderDataType::Assert(_)
+            | BuilderDataType::Divide(_)
+            | BuilderDataType::Try(_)
+            | BuilderDataType::ParseInt(_)
+            | BuilderDataType::ParseFloat(_)
+            | BuilderDataType::Tap(_)
+            | BuilderDataType::Quote(_)
+            | BuilderDataType::Replace(_)
+            | BuilderDataType::Path(_)
+            | BuilderDataType::Lazy(_)
+            | BuilderDataType::Comment(..)
+            | BuilderDataType::Shuffle(_)
+            | BuilderDataType::Toggle(_) => true,
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(_) => true,
+            BuilderDataType::Duration(_) => true,
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(_) | BuilderDataType::Capture(_) => true,
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true when every evaluation of this node yields the same value: it reads no
+    /// position, argument, binding or cell, and draws from no generator.
+    pub(crate) fn is_pure(&self) -> bool {
+        let mut pure = true;
+        self.walk(&mut |b| match b {
+            BuilderDataType::Index
+            | BuilderDataType::Unique
+            | BuilderDataType::Argument(_)
+            | BuilderDataType::ArgumentOr(..)
+            | BuilderDataType::TakeFromArgument(_)
+            | BuilderDataType::PopArgument
+            | BuilderDataType::SelfReference(_)
+            | BuilderDataType::SelfReferenceOr(..)
+            | BuilderDataType::Store(_)
+            | BuilderDataType::Take(_)
+            | BuilderDataType::Toggle(_)
+            | BuilderDataType::Var(_)
+            | BuilderDataType::CallFn(_)
+            | BuilderDataType::Weighted(_)
+            | BuilderDataType::Shuffle(_)
+            | BuilderDataType::Tap(_) => pure = false,
+            #[cfg(feature = "std-time")]
+            BuilderDataType::Now => pure = false,
+            _ => {}
+        });
+        pure
+    }
+
+    /// Whether a `Store` or `Take` is reachable from this node without passing through another
+    /// cell, so reading it may need the cell it came from.
+    pub(crate) fn has_cells(&self) -> bool {
+        match self {
+            BuilderDataType::Store(_) | BuilderDataType::Take(_) => true,
+            _ => {
+                let mut found = false;
+                self.for_each_child(&mut |child| found = found || child.has_cells());
+                found
+            }
+        }
+    }
+
+    /// Calls `f` on this node and on every node reachable from it.
+    ///
+    /// `Reference` and `Store` targets are visited every time they are reached, `SelfReference`
+    /// is never followed so cyclic graphs terminate.
+    pub fn walk(&self, f: &mut dyn FnMut(&BuilderDataType<'de>)) {
+        f(self);
+        self.for_each_child(&mut |child| child.walk(f));
+    }
+
+    /// Calls `f` on the direct children of this node, including `Reference` and `Store` targets.
+    pub(crate) fn for_each_child(&self, f: &mut dyn FnMut(&BuilderDataType<'de>)) {
+        match self {
+            BuilderDataType::Map(v) => v.iter().for_each(|(k, e)| {
+                f(k);
+                f(e);
+            }),
+            BuilderDataType::List(v)
+            | BuilderDataType::Closure(v)
+            | BuilderDataType::IfThenElse(v)
+            | BuilderDataType::Repeat(v)
+            | BuilderDataType::RepeatMap(v)
+            | BuilderDataType::Unfold(v)
+            | BuilderDataType::Interleave(v)
+            | BuilderDataType::Range(v)
+            | BuilderDataType::Sum(v)
+            | BuilderDataType::Multiply(v)
+            | BuilderDataType::Pad(v)
+            | BuilderDataType::Let(v)
+            | BuilderDataType::DefineFn(v)
+            | BuilderDataType::CallFn(v)
+            | BuilderDataType::Switch(v)
+            | BuilderDataType::Weighted(v)
+            | BuilderDataType::While(v)
+            | BuilderDataType::Scan(v)
+            | BuilderDataType::Partition(v)
+            | BuilderDataType::Assert(v)
+            | BuilderDataType::Divide(v)
+            | BuilderDataType::Try(v)
+            | BuilderDataType::ParseInt(v)
+            | BuilderDataType::ParseFloat(v)
+            | BuilderDataType::Tap(v)
+            | BuilderDataType::Quote(v)
+            | BuilderDataType::Replace(v)
+            | BuilderDataType::Variant(v)
+            | BuilderDataType::Path(v) => v.iter().for_each(f),
+            BuilderDataType::Apply(body, v) => {
+                f(body);
+                v.iter().for_each(f);
+            }
+            BuilderDataType::Var(n)
+            | BuilderDataType::ArgumentOr(_, n)
+            | BuilderDataType::SelfReferenceOr(_, n)
+            | BuilderDataType::Spread(n)
+            | BuilderDataType::Lazy(n)
+            | BuilderDataType::Comment(n, _)
+            | BuilderDataType::Shuffle(n) => f(n),
+            BuilderDataType::Reference(r) => f(r),
+            BuilderDataType::Store(r) | BuilderDataType::Take(r) => {
+                if let Ok(r) = r.try_borrow() {
+                    f(&r)
+                }
+            }
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(n) => f(n),
+            BuilderDataType::Duration(v) => v.iter().for_each(f),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) | BuilderDataType::Capture(v) => v.iter().for_each(f),
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => v.iter().for_each(f),
+            _ => {}
+        }
+    }
+
+    /// Appends `item` to a `List`. `Empty` becomes a one-element list, any other value becomes
+    /// the first element of a new list.
+    pub fn push(&mut self, item: BuilderDataType<'de>) {
+        match self {
+            BuilderDataType::List(v) => v.push(item),
+            BuilderDataType::Empty => *self = BuilderDataType::List(vec![item]),
+            other => {
+                let first = core::mem::replace(other, BuilderDataType::Empty);
+                *other = BuilderDataType::List(vec![first, item]);
+            }
+        }
+    }
+
+    /// Appends an entry to a `Map`. `Empty` becomes a one-entry map.
+    pub fn insert(
+        &mut self,
+        key: BuilderDataType<'de>,
+        value: BuilderDataType<'de>,
+    ) -> Result<(), BuilderError> {
+        match self {
+            BuilderDataType::Map(v) => v.push((key, value)),
+            BuilderDataType::Empty => *self = BuilderDataType::Map(vec![(key, value)]),
+            other => {
+                return Err(BuilderError::TypeMismatch {
+                    expected: "Map",
+                    found: other.kind(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes the next value out of a `Take` cell: a `List` yields its last element, a positive
+    /// counter its current value before counting down and a `Boolean` its value once. An
+    /// exhausted list or a counter at zero yields `Empty`.
     pub fn take_one(&mut self) -> BuilderDataType<'de> {
         match self {
             BuilderDataType::Empty => BuilderDataType::Empty,
@@ -42,20 +415,14 @@ impl<'de> BuilderDataType<'de> {
                 };
                 result
             }
-            BuilderDataType::Integer(b) => {
+            BuilderDataType::Integer(b) if *b > 0 => {
                 let result = BuilderDataType::Integer(*b);
-                if *b > 0 {
-                    *b -= 1
-                } else {
-                    *b = 0
-                }
+                *b -= 1;
                 result
             }
-            BuilderDataType::Unsigned(b) => {
+            BuilderDataType::Unsigned(b) if *b > 0 => {
                 let result = BuilderDataType::Unsigned(*b);
-                if *b > 0 {
-                    *b -= 1
-                }
+                *b -= 1;
                 result
             }
             BuilderDataType::List(c) => c.pop().unwrap_or(BuilderDataType::Empty),
@@ -63,6 +430,9 @@ impl<'de> BuilderDataType<'de> {
         }
     }
 
+    /// Reads a `Store` or `Take` cell in place; a cell that is already borrowed reads as false,
+    /// like the other conversions below read it as zero or empty. `Closure` evaluates cells
+    /// before converting them, reporting `BorrowConflict` instead.
     pub fn check_true(&self) -> bool {
         match self {
             BuilderDataType::Empty => false,
@@ -70,9 +440,11 @@ impl<'de> BuilderDataType<'de> {
             BuilderDataType::Integer(v) => *v != 0,
             BuilderDataType::Unsigned(v) => *v != 0,
             BuilderDataType::Number(v) => *v != 0.0,
+            BuilderDataType::Float32(v) => *v != 0.0,
             BuilderDataType::String(s) => !s.is_empty(),
             BuilderDataType::Map(c) => !c.is_empty(),
             BuilderDataType::List(c) => !c.is_empty(),
+            BuilderDataType::Bytes(c) => !c.is_empty(),
             BuilderDataType::Reference(r) => r.as_ref().check_true(),
             BuilderDataType::SelfReference(w) => {
                 if let Some(r) = w.upgrade() {
@@ -81,13 +453,18 @@ impl<'de> BuilderDataType<'de> {
                     false
                 }
             }
-            BuilderDataType::Store(r) => r.as_ref().borrow().check_true(),
-            BuilderDataType::Take(r) => r.as_ref().borrow_mut().take_one().check_true(),
-            BuilderDataType::Repeat(v) => v.first().map(|r| r.check_true()).unwrap_or(false),
+            BuilderDataType::Store(r) => r.try_borrow().is_ok_and(|r| r.check_true()),
+            BuilderDataType::Take(r) => r
+                .try_borrow_mut()
+                .is_ok_and(|mut r| r.take_one().check_true()),
+            BuilderDataType::Repeat(v) | BuilderDataType::RepeatMap(v) => {
+                v.first().map(|r| r.check_true()).unwrap_or(false)
+            }
             _ => false,
         }
     }
 
+    #[allow(clippy::collapsible_match)]
     pub fn to_unsigned(&self) -> u64 {
         match self {
             BuilderDataType::Empty => 0,
@@ -107,9 +484,17 @@ impl<'de> BuilderDataType<'de> {
                     0
                 }
             }
+            BuilderDataType::Float32(v) => {
+                if v.is_sign_positive() && v.is_normal() {
+                    *v as u64
+                } else {
+                    0
+                }
+            }
             BuilderDataType::String(v) => v.parse().unwrap_or(0),
             BuilderDataType::Map(v) => v.len() as u64,
             BuilderDataType::List(v) => v.len() as u64,
+            BuilderDataType::Bytes(v) => v.len() as u64,
             BuilderDataType::Reference(r) => r.as_ref().to_unsigned(),
             BuilderDataType::SelfReference(w) => {
                 if let Some(r) = w.upgrade() {
@@ -118,13 +503,18 @@ impl<'de> BuilderDataType<'de> {
                     0
                 }
             }
-            BuilderDataType::Store(r) => r.as_ref().borrow().to_unsigned(),
-            BuilderDataType::Take(r) => r.as_ref().borrow_mut().take_one().to_unsigned(),
-            BuilderDataType::Repeat(v) => v.first().map(|r| r.to_unsigned()).unwrap_or(0),
+            BuilderDataType::Store(r) => r.try_borrow().map_or(0, |r| r.to_unsigned()),
+            BuilderDataType::Take(r) => r
+                .try_borrow_mut()
+                .map_or(0, |mut r| r.take_one().to_unsigned()),
+            BuilderDataType::Repeat(v) | BuilderDataType::RepeatMap(v) => {
+                v.first().map(|r| r.to_unsigned()).unwrap_or(0)
+            }
             _ => 0,
         }
     }
 
+    #[allow(clippy::collapsible_match)]
     pub fn to_signed(&self) -> i64 {
         match self {
             BuilderDataType::Empty => 0,
@@ -144,9 +534,17 @@ impl<'de> BuilderDataType<'de> {
                     0
                 }
             }
+            BuilderDataType::Float32(v) => {
+                if v.is_normal() {
+                    *v as i64
+                } else {
+                    0
+                }
+            }
             BuilderDataType::String(v) => v.parse().unwrap_or(0),
             BuilderDataType::Map(v) => v.len() as i64,
             BuilderDataType::List(v) => v.len() as i64,
+            BuilderDataType::Bytes(v) => v.len() as i64,
             BuilderDataType::Reference(r) => r.as_ref().to_signed(),
             BuilderDataType::SelfReference(w) => {
                 if let Some(r) = w.upgrade() {
@@ -155,13 +553,18 @@ impl<'de> BuilderDataType<'de> {
                     0
                 }
             }
-            BuilderDataType::Store(r) => r.as_ref().borrow().to_signed(),
-            BuilderDataType::Take(r) => r.as_ref().borrow_mut().take_one().to_signed(),
-            BuilderDataType::Repeat(v) => v.first().map(|r| r.to_signed()).unwrap_or(0),
+            BuilderDataType::Store(r) => r.try_borrow().map_or(0, |r| r.to_signed()),
+            BuilderDataType::Take(r) => r
+                .try_borrow_mut()
+                .map_or(0, |mut r| r.take_one().to_signed()),
+            BuilderDataType::Repeat(v) | BuilderDataType::RepeatMap(v) => {
+                v.first().map(|r| r.to_signed()).unwrap_or(0)
+            }
             _ => 0,
         }
     }
 
+    #[allow(clippy::collapsible_match)]
     pub fn to_float(&self) -> f64 {
         match self {
             BuilderDataType::Empty => 0.0,
@@ -175,9 +578,11 @@ impl<'de> BuilderDataType<'de> {
             BuilderDataType::Integer(v) => *v as f64,
             BuilderDataType::Unsigned(v) => *v as f64,
             BuilderDataType::Number(v) => *v,
+            BuilderDataType::Float32(v) => f64::from(*v),
             BuilderDataType::String(v) => v.parse().unwrap_or(0.0),
             BuilderDataType::Map(v) => v.len() as f64,
             BuilderDataType::List(v) => v.len() as f64,
+            BuilderDataType::Bytes(v) => v.len() as f64,
             BuilderDataType::Reference(r) => r.as_ref().to_float(),
             BuilderDataType::SelfReference(w) => {
                 if let Some(r) = w.upgrade() {
@@ -186,9 +591,13 @@ impl<'de> BuilderDataType<'de> {
                     0.0
                 }
             }
-            BuilderDataType::Store(r) => r.as_ref().borrow().to_float(),
-            BuilderDataType::Take(r) => r.as_ref().borrow_mut().take_one().to_float(),
-            BuilderDataType::Repeat(v) => v.first().map(|r| r.to_float()).unwrap_or(0.0),
+            BuilderDataType::Store(r) => r.try_borrow().map_or(0.0, |r| r.to_float()),
+            BuilderDataType::Take(r) => r
+                .try_borrow_mut()
+                .map_or(0.0, |mut r| r.take_one().to_float()),
+            BuilderDataType::Repeat(v) | BuilderDataType::RepeatMap(v) => {
+                v.first().map(|r| r.to_float()).unwrap_or(0.0)
+            }
             _ => 0.0,
         }
     }
@@ -206,7 +615,12 @@ impl<'de> BuilderDataType<'de> {
             BuilderDataType::Integer(v) => Cow::Owned(format!("{}", *v)),
             BuilderDataType::Unsigned(v) => Cow::Owned(format!("{}", *v)),
             BuilderDataType::Number(v) => Cow::Owned(format!("{}", *v)),
+            BuilderDataType::Float32(v) => Cow::Owned(format!("{}", *v)),
             BuilderDataType::String(v) => v.clone(),
+            BuilderDataType::Bytes(Cow::Borrowed(v)) => String::from_utf8_lossy(v),
+            BuilderDataType::Bytes(Cow::Owned(v)) => {
+                Cow::Owned(String::from_utf8_lossy(v).into_owned())
+            }
             BuilderDataType::Map(v) => v.iter().fold(Cow::Owned(String::new()), |s, e| {
                 let key = e.0.to_string();
                 if key.is_empty() {
@@ -242,15 +656,188 @@ impl<'de> BuilderDataType<'de> {
                     Cow::Owned(String::new())
                 }
             }
-            BuilderDataType::Store(r) => r.as_ref().borrow().to_string(),
-            BuilderDataType::Take(r) => r.as_ref().borrow_mut().take_one().to_string(),
+            BuilderDataType::Store(r) => {
+                r.try_borrow().map_or(Cow::Borrowed(""), |r| r.to_string())
+            }
+            BuilderDataType::Take(r) => r
+                .try_borrow_mut()
+                .map_or(Cow::Borrowed(""), |mut r| r.take_one().to_string()),
             _ => Cow::Owned(String::new()),
         }
     }
 }
 
+/// Builds a plain data graph from any self-describing input, borrowing strings and bytes from it
+/// where the format allows.
 struct DataTypeVisitor<'de> {
-    data: BuilderDataType<'de>
+    marker: PhantomData<BuilderDataType<'de>>,
+}
+
+impl<'de> serde::de::Visitor<'de> for DataTypeVisitor<'de> {
+    type Value = BuilderDataType<'de>;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Unsigned(v))
+    }
+
+    fn visit_f32<E>(self, v: f32) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Float32(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Number(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::String(Cow::Owned(v.into())))
+    }
+
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::String(Cow::Borrowed(v)))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::String(Cow::Owned(v)))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Bytes(Cow::Owned(v.into())))
+    }
+
+    fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Bytes(Cow::Borrowed(v)))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Bytes(Cow::Owned(v)))
+    }
+
+    fn visit_none<E>(self) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Empty)
+    }
+
+    fn visit_unit<E>(self) -> Result<Self::Value, E> {
+        Ok(BuilderDataType::Empty)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        serde::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        serde::Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::SeqAccess<'de>,
+    {
+        let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(SIZE_HINT_LIMIT));
+        while let Some(e) = seq.next_element()? {
+            v.push(e);
+        }
+        Ok(BuilderDataType::List(v))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut v = Vec::with_capacity(map.size_hint().unwrap_or(0).min(SIZE_HINT_LIMIT));
+        while let Some(e) = map.next_entry()? {
+            v.push(e);
+        }
+        Ok(BuilderDataType::Map(v))
+    }
+}
+
+impl<'de> BuilderDataType<'de> {
+    /// Follows references to a scalar or string value, failing with a `TypeMismatch` that names
+    /// `expected` for anything else.
+    fn into_scalar(self, expected: &'static str) -> Result<BuilderDataType<'de>, BuilderError> {
+        match self {
+            BuilderDataType::Reference(r) => Rc::unwrap_or_clone(r).into_scalar(expected),
+            BuilderDataType::SelfReference(w) => w
+                .upgrade()
+                .ok_or(BuilderError::InvalidSelfRefrence)?
+                .as_ref()
+                .clone()
+                .into_scalar(expected),
+            BuilderDataType::Store(r) => read_cell(&r)?.clone().into_scalar(expected),
+            b @ (BuilderDataType::Boolean(_)
+            | BuilderDataType::Integer(_)
+            | BuilderDataType::Unsigned(_)
+            | BuilderDataType::Number(_)
+            | BuilderDataType::Float32(_)
+            | BuilderDataType::String(_)) => Ok(b),
+            b => Err(BuilderError::TypeMismatch {
+                expected,
+                found: b.kind(),
+            }),
+        }
+    }
+}
+
+/// Converts scalars with the matching `to_*` helper; strings must parse as the target.
+macro_rules! try_from_scalar {
+    ($($target:ty => $to:ident),*) => {$(
+        impl TryFrom<BuilderDataType<'_>> for $target {
+            type Error = BuilderError;
+
+            fn try_from(value: BuilderDataType<'_>) -> Result<Self, Self::Error> {
+                match value.into_scalar(stringify!($target))? {
+                    BuilderDataType::String(s) => s
+                        .trim()
+                        .parse()
+                        .map_err(|_| BuilderError::ParseFailed(s.into_owned())),
+                    b => Ok(b.$to()),
+                }
+            }
+        }
+    )*};
+}
+
+try_from_scalar!(i64 => to_signed, u64 => to_unsigned, f64 => to_float);
+
+impl TryFrom<BuilderDataType<'_>> for bool {
+    type Error = BuilderError;
+
+    fn try_from(value: BuilderDataType<'_>) -> Result<Self, Self::Error> {
+        Ok(value.into_scalar("bool")?.check_true())
+    }
+}
+
+impl TryFrom<BuilderDataType<'_>> for String {
+    type Error = BuilderError;
+
+    fn try_from(value: BuilderDataType<'_>) -> Result<Self, Self::Error> {
+        Ok(value.into_scalar("String")?.to_string().into_owned())
+    }
+}
+
+impl<'de> Extend<BuilderDataType<'de>> for BuilderDataType<'de> {
+    /// Pushes every item, converting `self` to a `List` as `push` does.
+    fn extend<T: IntoIterator<Item = BuilderDataType<'de>>>(&mut self, iter: T) {
+        iter.into_iter().for_each(|item| self.push(item));
+    }
 }
 
 impl<'de> serde::Deserialize<'de> for BuilderDataType<'de> {
@@ -258,6 +845,8 @@ impl<'de> serde::Deserialize<'de> for BuilderDataType<'de> {
     where
         D: serde::Deserializer<'de>,
     {
-        todo!("deserializer.deserialize_any(visitor)")
+        deserializer.deserialize_any(DataTypeVisitor {
+            marker: PhantomData,
+        })
     }
 }
@@ -0,0 +1,162 @@
+use crate::BuilderDataType;
+use alloc::borrow::{Borrow, ToOwned};
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Structure of one node: its variant, its own payload and the ids of its children. Two nodes
+/// get the same id exactly when their shapes are equal, so comparing a node only looks at its
+/// direct children.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Shape {
+    kind: &'static str,
+    leaf: u64,
+    children: Vec<usize>,
+}
+
+struct Deduper<'de> {
+    /// Id of every distinct shape seen.
+    shapes: BTreeMap<Shape, usize>,
+    /// Ids of the string and byte payloads, so a shape does not hold a copy of each one.
+    strings: BTreeMap<String, u64>,
+    bytes: BTreeMap<Vec<u8>, u64>,
+    /// Id of each node in pre-order, `None` for nodes whose identity matters, such as `Store`
+    /// cells, and for the nodes holding them, so they are never merged.
+    ids: Vec<Option<usize>>,
+    /// Number of nodes in each subtree in pre-order, for stepping over the subtrees `share`
+    /// keeps as they are.
+    sizes: Vec<usize>,
+    /// Occurrences of each id among the `List`/`Map` nodes `share` reaches.
+    counts: Vec<usize>,
+    /// Pre-order position of the next node `share` reaches.
+    position: usize,
+    /// Shared copies of the subtrees seen more than once.
+    shared: BTreeMap<usize, Rc<BuilderDataType<'de>>>,
+}
+
+fn intern<T>(table: &mut BTreeMap<T::Owned, u64>, value: &T) -> u64
+where
+    T: Ord + ToOwned + ?Sized,
+    T::Owned: Ord + Borrow<T>,
+{
+    if let Some(&id) = table.get(value) {
+        return id;
+    }
+    let id = table.len() as u64;
+    table.insert(value.to_owned(), id);
+    id
+}
+
+impl<'de> Deduper<'de> {
+    /// Payload of `node` besides its children.
+    fn leaf(&mut self, node: &BuilderDataType<'de>) -> u64 {
+        match node {
+            BuilderDataType::Boolean(b) => u64::from(*b),
+            BuilderDataType::Integer(i) => *i as u64,
+            BuilderDataType::Unsigned(u) => *u,
+            BuilderDataType::Number(n) => n.to_bits(),
+            BuilderDataType::Float32(n) => u64::from(n.to_bits()),
+            BuilderDataType::String(s) => intern(&mut self.strings, s.as_ref()),
+            BuilderDataType::Comment(_, note) => intern(&mut self.strings, note.as_ref()),
+            BuilderDataType::Bytes(b) => intern(&mut self.bytes, b.as_ref()),
+            BuilderDataType::Argument(a)
+            | BuilderDataType::ArgumentOr(a, _)
+            | BuilderDataType::TakeFromArgument(a) => *a as u64,
+            _ => 0,
+        }
+    }
+
+    /// Assigns ids to `node` and its subtree, bottom-up, counting the `List`/`Map` nodes when
+    /// `reached` says `share` will get to them.
+    fn count(&mut self, node: &BuilderDataType<'de>, reached: bool) -> Option<usize> {
+        let at = self.ids.len();
+        self.ids.push(None);
+        self.sizes.push(1);
+        if matches!(
+            node,
+            BuilderDataType::Reference(_)
+                | BuilderDataType::SelfReference(_)
+                | BuilderDataType::SelfReferenceOr(..)
+                | BuilderDataType::Store(_)
+                | BuilderDataType::Take(_)
+                | BuilderDataType::Toggle(_)
+        ) {
+            return None;
+        }
+        let container = matches!(node, BuilderDataType::List(_) | BuilderDataType::Map(_));
+        let mut children = Some(Vec::new());
+        node.for_each_child(&mut |child| {
+            let id = self.count(child, reached && container);
+            match (id, &mut children) {
+                (Some(id), Some(children)) => children.push(id),
+                _ => children = None,
+            }
+        });
+        self.sizes[at] = self.ids.len() - at;
+        let shape = Shape {
+            kind: node.kind(),
+            leaf: self.leaf(node),
+            children: children?,
+        };
+        let next = self.shapes.len();
+        let id = *self.shapes.entry(shape).or_insert(next);
+        self.ids[at] = Some(id);
+        if reached && container {
+            self.counts.resize(self.shapes.len(), 0);
+            self.counts[id] += 1;
+        }
+        Some(id)
+    }
+
+    fn share(&mut self, node: BuilderDataType<'de>) -> BuilderDataType<'de> {
+        let at = self.position;
+        self.position += 1;
+        let id = self.ids[at].filter(|&id| self.counts.get(id).is_some_and(|&n| n > 1));
+        if let Some(shared) = id.and_then(|id| self.shared.get(&id)) {
+            self.position = at + self.sizes[at];
+            return BuilderDataType::Reference(shared.clone());
+        }
+        let node = match node {
+            BuilderDataType::List(v) => {
+                BuilderDataType::List(v.into_iter().map(|e| self.share(e)).collect())
+            }
+            BuilderDataType::Map(v) => BuilderDataType::Map(
+                v.into_iter()
+                    .map(|(k, e)| (self.share(k), self.share(e)))
+                    .collect(),
+            ),
+            node => {
+                self.position = at + self.sizes[at];
+                node
+            }
+        };
+        match id {
+            Some(id) => {
+                let shared = Rc::new(node);
+                self.shared.insert(id, shared.clone());
+                BuilderDataType::Reference(shared)
+            }
+            None => node,
+        }
+    }
+}
+
+impl<'de> BuilderDataType<'de> {
+    /// Replaces repeated, structurally equal `List` and `Map` subtrees with one shared
+    /// `Reference`, so the graph takes less memory and is read through the shared path.
+    pub fn dedup(self) -> BuilderDataType<'de> {
+        let mut deduper = Deduper {
+            shapes: BTreeMap::new(),
+            strings: BTreeMap::new(),
+            bytes: BTreeMap::new(),
+            ids: Vec::new(),
+            sizes: Vec::new(),
+            counts: Vec::new(),
+            position: 0,
+            shared: BTreeMap::new(),
+        };
+        deduper.count(&self, true);
+        deduper.share(self)
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{BuilderDataType, BuilderDeserializer, BuilderError, Closure};
+use serde::de::{DeserializeSeed, EnumAccess, Error, Unexpected, VariantAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+pub struct BuilderEnumAccess<'s, 'de> {
+    pub(crate) closure: &'s mut Closure<'de>,
+    pub(crate) variant: BuilderDataType<'de>,
+    pub(crate) value: Option<BuilderDataType<'de>>,
+}
+
+pub struct BuilderVariantAccess<'s, 'de> {
+    pub(crate) closure: &'s mut Closure<'de>,
+    pub(crate) value: Option<BuilderDataType<'de>>,
+}
+
+impl<'s, 'de> EnumAccess<'de> for BuilderEnumAccess<'s, 'de> {
+    type Error = BuilderError;
+    type Variant = BuilderVariantAccess<'s, 'de>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let BuilderEnumAccess {
+            closure,
+            variant,
+            value,
+        } = self;
+        let v = seed.deserialize(BuilderDeserializer {
+            closure: &mut *closure,
+            data: variant,
+        })?;
+        Ok((v, BuilderVariantAccess { closure, value }))
+    }
+}
+
+impl<'s, 'de> VariantAccess<'de> for BuilderVariantAccess<'s, 'de> {
+    type Error = BuilderError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        match self.value {
+            None => Ok(()),
+            Some(data) => <()>::deserialize(BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value {
+            Some(data) => seed.deserialize(BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }),
+            None => Err(BuilderError::invalid_type(
+                Unexpected::UnitVariant,
+                &"newtype variant",
+            )),
+        }
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Some(data) => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_tuple(len, visitor),
+            None => Err(BuilderError::invalid_type(
+                Unexpected::UnitVariant,
+                &"tuple variant",
+            )),
+        }
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Some(data) => BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }
+            .deserialize_struct("", fields, visitor),
+            None => Err(BuilderError::invalid_type(
+                Unexpected::UnitVariant,
+                &"struct variant",
+            )),
+        }
+    }
+}
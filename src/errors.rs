@@ -1,16 +1,73 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::fmt::Display;
 use serde::de::Error;
-use std::fmt::Display;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BuilderError {
     InvalidMapAccess,
     InvalidDeserialization(String),
     InvalidFunctionArgument,
     InvalidSelfRefrence,
+    UnboundVariable(String),
+    /// A `CallFn` names no function defined by an enclosing `DefineFn`.
+    UndefinedFunction(String),
+    IterationLimit(usize),
+    /// Data nested deeper than `DeserializerConfig::recursion_limit` levels.
+    RecursionLimit(usize),
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    UnsupportedVariant(&'static str),
+    AssertionFailed(String),
+    DivisionByZero,
+    DuplicateKey(String),
+    ParseFailed(String),
+    ArrayLengthMismatch {
+        expected: usize,
+        found: usize,
+    },
+    /// An operation got the wrong number of operands; `expected` is a minimum for `Repeat`.
+    WrongArity {
+        op: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// A negative `Integer` was deserialized into an unsigned field.
+    NegativeToUnsigned {
+        value: i64,
+    },
+    /// The weights of a `Weighted` add up to zero.
+    ZeroTotalWeight,
+    /// A `Number` is too large for an `f32` field.
+    FloatOverflow(f64),
+    /// A `Matches` or `Capture` pattern is not a valid regular expression.
+    InvalidPattern(String),
+    /// A `FormatDate` format string has an unknown or malformed specifier.
+    InvalidDateFormat(String),
+    /// A `Take` reached a `Store` that is being read, or the other way around.
+    BorrowConflict,
+    /// A `Closure` has no body.
+    EmptyClosure,
+    /// A `SelfReference` reaches its own target without passing through a conditional.
+    UnguardedCycle,
+    /// A closure body reads more arguments than it is given, found before evaluating the body
+    /// when `DeserializerConfig::check_arity` is on.
+    ArgCountMismatch {
+        needed: usize,
+        provided: usize,
+    },
+    /// Evaluating operand `index` of `op` failed with `source`.
+    Context {
+        op: &'static str,
+        index: usize,
+        source: Box<BuilderError>,
+    },
 }
 
 impl Display for BuilderError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             BuilderError::InvalidMapAccess => {
                 f.write_fmt(format_args!("Invalid map access sequence."))
@@ -18,13 +75,83 @@ impl Display for BuilderError {
             BuilderError::InvalidDeserialization(err) => {
                 f.write_fmt(format_args!("Invalid deserialization: {err}"))
             }
-            BuilderError::InvalidFunctionArgument => todo!(),
-            BuilderError::InvalidSelfRefrence => todo!(),
+            BuilderError::InvalidFunctionArgument => {
+                f.write_fmt(format_args!("Invalid function argument."))
+            }
+            BuilderError::InvalidSelfRefrence => {
+                f.write_fmt(format_args!("Invalid self reference."))
+            }
+            BuilderError::UnboundVariable(name) => {
+                f.write_fmt(format_args!("Unbound variable: {name}"))
+            }
+            BuilderError::UndefinedFunction(name) => {
+                f.write_fmt(format_args!("Undefined function: {name}"))
+            }
+            BuilderError::IterationLimit(limit) => {
+                f.write_fmt(format_args!("Iteration limit of {limit} exceeded."))
+            }
+            BuilderError::RecursionLimit(limit) => {
+                f.write_fmt(format_args!("Recursion limit of {limit} exceeded."))
+            }
+            BuilderError::TypeMismatch { expected, found } => f.write_fmt(format_args!(
+                "Type mismatch: expected {expected}, found {found}."
+            )),
+            BuilderError::UnsupportedVariant(kind) => {
+                f.write_fmt(format_args!("Unsupported variant: {kind}"))
+            }
+            BuilderError::AssertionFailed(message) => {
+                f.write_fmt(format_args!("Assertion failed: {message}"))
+            }
+            BuilderError::DivisionByZero => f.write_fmt(format_args!("Division by zero.")),
+            BuilderError::DuplicateKey(key) => {
+                f.write_fmt(format_args!("Duplicate map key: {key}"))
+            }
+            BuilderError::ParseFailed(input) => {
+                f.write_fmt(format_args!("Failed to parse number: {input}"))
+            }
+            BuilderError::ArrayLengthMismatch { expected, found } => f.write_fmt(format_args!(
+                "Array length mismatch: expected {expected} elements, found {found}."
+            )),
+            BuilderError::WrongArity {
+                op,
+                expected,
+                found,
+            } => f.write_fmt(format_args!(
+                "Wrong arity for {op}: expected {expected} operands, found {found}."
+            )),
+            BuilderError::NegativeToUnsigned { value } => f.write_fmt(format_args!(
+                "Negative integer {value} for an unsigned field."
+            )),
+            BuilderError::ZeroTotalWeight => f.write_fmt(format_args!("Weights add up to zero.")),
+            BuilderError::FloatOverflow(v) => {
+                f.write_fmt(format_args!("Number {v} overflows f32."))
+            }
+            BuilderError::InvalidPattern(pattern) => {
+                f.write_fmt(format_args!("Invalid regular expression: {pattern}"))
+            }
+            BuilderError::InvalidDateFormat(format) => {
+                f.write_fmt(format_args!("Invalid date format: {format}"))
+            }
+            BuilderError::BorrowConflict => {
+                f.write_fmt(format_args!("Store is taken from while it is being read."))
+            }
+            BuilderError::EmptyClosure => f.write_fmt(format_args!(
+                "Closure has no body; its first element is the body."
+            )),
+            BuilderError::UnguardedCycle => {
+                f.write_fmt(format_args!("Unguarded self reference cycle."))
+            }
+            BuilderError::ArgCountMismatch { needed, provided } => f.write_fmt(format_args!(
+                "Closure body needs {needed} arguments, {provided} provided."
+            )),
+            BuilderError::Context { op, index, source } => f.write_fmt(format_args!(
+                "while evaluating {op} operand {index}: {source}"
+            )),
         }
     }
 }
 
-impl std::error::Error for BuilderError {}
+impl core::error::Error for BuilderError {}
 
 impl Error for BuilderError {
     fn custom<T>(msg: T) -> Self
@@ -1,3 +1,7 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#[macro_use]
+extern crate alloc;
+
 mod errors;
 pub use errors::BuilderError;
 mod datatype;
@@ -13,42 +17,145 @@ pub use list_access::BuilderListAccess;
 mod list_access_ref;
 pub use list_access_ref::BuilderListAccessRef;
 mod map_access;
-pub use map_access::BuilderMapAccess;
+pub use map_access::{BuilderMapAccess, DuplicateKeyPolicy};
 mod map_access_ref;
 pub use map_access_ref::BuilderMapAccessRef;
+mod enum_access;
+pub use enum_access::{BuilderEnumAccess, BuilderVariantAccess};
+mod while_access;
+pub use while_access::BuilderWhileAccess;
+mod repeat_map_access;
+pub use repeat_map_access::BuilderRepeatMapAccess;
+mod unfold_access;
+pub use unfold_access::BuilderUnfoldAccess;
+mod stats;
+pub use stats::Stats;
+mod config;
+mod dedup;
+mod pretty;
+mod rng;
+mod template;
+mod validate;
+pub use config::DeserializerConfig;
 use serde::Deserialize;
+pub use template::Template;
 
 pub fn from_data<'a, T>(data: BuilderDataType<'a>) -> Result<T, BuilderError>
 where
     T: Deserialize<'a>,
 {
-    let mut closure = Closure {
-        args: Vec::new(),
-        index: 0,
-    };
+    let mut closure = Closure::new();
     let builder = BuilderDeserializer {
         closure: &mut closure,
         data,
     };
 
-    Ok(T::deserialize(builder)?)
+    T::deserialize(builder)
 }
 
 pub fn from_ref<'a, T>(data: &BuilderDataType<'a>) -> Result<T, BuilderError>
 where
     T: Deserialize<'a>,
 {
-    let mut closure = Closure {
-        args: Vec::new(),
-        index: 0,
-    };
+    let mut closure = Closure::new();
     let builder = BuilderDeserializerRef {
         closure: &mut closure,
         data,
     };
 
-    Ok(T::deserialize(builder)?)
+    T::deserialize(builder)
+}
+
+pub fn from_data_strict<'a, T>(data: BuilderDataType<'a>) -> Result<T, BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    DeserializerConfig::new().strict(true).deserialize(data)
+}
+
+/// Like `from_data`, but serves the entries of every `Map` sorted by the string form of their
+/// keys, so visitors see them in a reproducible order.
+pub fn from_data_sorted_maps<'a, T>(data: BuilderDataType<'a>) -> Result<T, BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    DeserializerConfig::new().sort_maps(true).deserialize(data)
+}
+
+/// Like `from_data`, but calls `tap` with the label and value of every `Tap` node evaluated.
+pub fn from_data_with_tap<'a, T>(
+    data: BuilderDataType<'a>,
+    tap: impl Fn(&str, &str) + 'static,
+) -> Result<T, BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    DeserializerConfig::new().tap(tap).deserialize(data)
+}
+
+/// Like `from_data`, but also returns counters describing the work done.
+pub fn from_data_with_stats<'a, T>(data: BuilderDataType<'a>) -> Result<(T, Stats), BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    let stats = alloc::rc::Rc::new(core::cell::RefCell::new(Stats::default()));
+    let value = DeserializerConfig::new()
+        .stats(stats.clone())
+        .deserialize(data)?;
+    Ok((value, stats.take()))
+}
+
+pub fn from_data_with_duplicate_keys<'a, T>(
+    data: BuilderDataType<'a>,
+    policy: DuplicateKeyPolicy,
+) -> Result<T, BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    DeserializerConfig::new()
+        .duplicate_keys(policy)
+        .deserialize(data)
+}
+
+/// Evaluates `data` to a `Map` and returns an access over its entries, so they can be
+/// deserialized one at a time.
+pub fn to_map_access<'s, 'de>(
+    closure: &'s mut Closure<'de>,
+    data: BuilderDataType<'de>,
+) -> Result<
+    BuilderMapAccess<'s, 'de, alloc::vec::IntoIter<(BuilderDataType<'de>, BuilderDataType<'de>)>>,
+    BuilderError,
+> {
+    match closure.evaluate(data)? {
+        BuilderDataType::Map(v) => Ok(BuilderMapAccess::new(closure, v.into_iter())),
+        other => Err(BuilderError::TypeMismatch {
+            expected: "Map",
+            found: other.kind(),
+        }),
+    }
+}
+
+#[cfg(feature = "std-time")]
+pub fn from_data_with_clock<'a, T>(
+    data: BuilderDataType<'a>,
+    clock: fn() -> u64,
+) -> Result<T, BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    DeserializerConfig::new().clock(clock).deserialize(data)
+}
+
+#[cfg(feature = "std-env")]
+pub fn from_data_with_env<'a, T>(
+    data: BuilderDataType<'a>,
+    env: std::collections::HashMap<String, String>,
+) -> Result<T, BuilderError>
+where
+    T: Deserialize<'a>,
+{
+    DeserializerConfig::new().env(env).deserialize(data)
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests;
@@ -1,4 +1,5 @@
 use crate::{BuilderDataType, BuilderDeserializer, BuilderError, Closure};
+use alloc::vec::Vec;
 use serde::de::{DeserializeSeed, SeqAccess};
 
 pub struct BuilderListAccess<'s, 'de, I>
@@ -9,6 +10,46 @@ where
     pub(crate) data: I,
     pub(crate) size_hint: Option<usize>,
     pub(crate) index: usize,
+    pub(crate) pending: Vec<BuilderDataType<'de>>,
+}
+
+impl<'s, 'de, I> BuilderListAccess<'s, 'de, I>
+where
+    I: Iterator<Item = BuilderDataType<'de>>,
+{
+    /// Creates a sequence access over `data` that can be driven manually with
+    /// `next_element_seed`.
+    pub fn new(closure: &'s mut Closure<'de>, data: I) -> Self {
+        let size_hint = match data.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        };
+        BuilderListAccess {
+            closure,
+            data,
+            size_hint,
+            index: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the next element, expanding `Spread` elements into the elements of their list
+    /// and leaving out `Empty` when the closure skips it.
+    fn next_raw(&mut self) -> Result<Option<BuilderDataType<'de>>, BuilderError> {
+        loop {
+            let Some(data) = self.pending.pop().or_else(|| self.data.next()) else {
+                return Ok(None);
+            };
+            match data {
+                BuilderDataType::Spread(target) => {
+                    let elements = self.closure.resolve_list(*target)?;
+                    self.pending.extend(elements.into_iter().rev());
+                }
+                BuilderDataType::Empty if self.closure.skip_empty => {}
+                data => return Ok(Some(data)),
+            }
+        }
+    }
 }
 
 impl<'s, 'de, I> SeqAccess<'de> for BuilderListAccess<'s, 'de, I>
@@ -21,13 +62,16 @@ where
     where
         T: DeserializeSeed<'de>,
     {
-        if let Some(data) = self.data.next() {
+        if let Some(data) = self.next_raw()? {
+            let outer = self.closure.index;
             self.closure.index = self.index;
             self.index += 1;
-            Ok(Some(seed.deserialize(BuilderDeserializer {
+            let value = seed.deserialize(BuilderDeserializer {
                 closure: self.closure,
                 data,
-            })?))
+            });
+            self.closure.index = outer;
+            Ok(Some(value?))
         } else {
             Ok(None)
         }
@@ -37,3 +81,43 @@ where
         self.size_hint
     }
 }
+
+/// Yields one element from each iterator in turn, skipping iterators once they run out.
+pub(crate) struct RoundRobin<I> {
+    iters: Vec<I>,
+    next: usize,
+}
+
+impl<I: Iterator> RoundRobin<I> {
+    pub(crate) fn new(iters: Vec<I>) -> Self {
+        RoundRobin { iters, next: 0 }
+    }
+}
+
+impl<I: Iterator> Iterator for RoundRobin<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.iters.is_empty() {
+            let i = self.next % self.iters.len();
+            match self.iters[i].next() {
+                Some(item) => {
+                    self.next = i + 1;
+                    return Some(item);
+                }
+                None => {
+                    self.iters.remove(i);
+                    self.next = i;
+                }
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iters.iter().fold((0, Some(0)), |(lower, upper), i| {
+            let (l, u) = i.size_hint();
+            (lower + l, upper.zip(u).map(|(a, b)| a + b))
+        })
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{BuilderDataType, BuilderDeserializerRef, BuilderError, Closure};
+use crate::{BuilderDataType, BuilderDeserializer, BuilderDeserializerRef, BuilderError, Closure};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
 use serde::de::{DeserializeSeed, SeqAccess};
 
 pub struct BuilderListAccessRef<'s, 'r, 'de, I>
@@ -10,6 +12,34 @@ where
     pub(crate) data: I,
     pub(crate) size_hint: Option<usize>,
     pub(crate) index: usize,
+    pub(crate) pending: Vec<BuilderDataType<'de>>,
+}
+
+impl<'s, 'r, 'de, I> BuilderListAccessRef<'s, 'r, 'de, I>
+where
+    I: Iterator<Item = &'r BuilderDataType<'de>>,
+{
+    /// Returns the next element, expanding `Spread` elements into owned copies of the elements
+    /// of their list and leaving out `Empty` when the closure skips it.
+    fn next_raw(&mut self) -> Result<Option<Cow<'r, BuilderDataType<'de>>>, BuilderError> {
+        loop {
+            let data = match self.pending.pop() {
+                Some(data) => Cow::Owned(data),
+                None => match self.data.next() {
+                    Some(data) => Cow::Borrowed(data),
+                    None => return Ok(None),
+                },
+            };
+            match data.as_ref() {
+                BuilderDataType::Spread(target) => {
+                    let elements = self.closure.resolve_list(target.as_ref().clone())?;
+                    self.pending.extend(elements.into_iter().rev());
+                }
+                BuilderDataType::Empty if self.closure.skip_empty => {}
+                _ => return Ok(Some(data)),
+            }
+        }
+    }
 }
 
 impl<'s, 'r, 'de, I> SeqAccess<'de> for BuilderListAccessRef<'s, 'r, 'de, I>
@@ -22,13 +52,22 @@ where
     where
         T: DeserializeSeed<'de>,
     {
-        if let Some(data) = self.data.next() {
+        if let Some(data) = self.next_raw()? {
+            let outer = self.closure.index;
             self.closure.index = self.index;
             self.index += 1;
-            Ok(Some(seed.deserialize(BuilderDeserializerRef {
-                closure: self.closure,
-                data,
-            })?))
+            let value = match data {
+                Cow::Borrowed(data) => seed.deserialize(BuilderDeserializerRef {
+                    closure: self.closure,
+                    data,
+                }),
+                Cow::Owned(data) => seed.deserialize(BuilderDeserializer {
+                    closure: self.closure,
+                    data,
+                }),
+            };
+            self.closure.index = outer;
+            Ok(Some(value?))
         } else {
             Ok(None)
         }
@@ -1,6 +1,31 @@
 use crate::{BuilderDataType, BuilderDeserializer, BuilderError, Closure};
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
 use serde::de::{DeserializeSeed, MapAccess};
 
+/// What map access does when a key repeats within one map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateKeyPolicy {
+    /// Yield every entry, leaving the choice to the visitor. Most maps keep the last value.
+    #[default]
+    Last,
+    /// Skip entries whose key was already yielded.
+    First,
+    /// Fail with `BuilderError::DuplicateKey`.
+    Error,
+    /// Deep-merge repeated keys whose values are both maps into one entry. Other repeated keys
+    /// keep the last value.
+    Merge,
+}
+
+impl DuplicateKeyPolicy {
+    /// Whether every entry reaches the visitor unfiltered.
+    pub(crate) fn yields_all(self) -> bool {
+        matches!(self, DuplicateKeyPolicy::Last | DuplicateKeyPolicy::Merge)
+    }
+}
+
 pub struct BuilderMapAccess<'s, 'de, I>
 where
     I: Iterator<Item = (BuilderDataType<'de>, BuilderDataType<'de>)>,
@@ -9,6 +34,45 @@ where
     pub(crate) data: I,
     pub(crate) leftover: Option<BuilderDataType<'de>>,
     pub(crate) size_hint: Option<usize>,
+    pub(crate) seen: BTreeSet<String>,
+    pub(crate) pending: Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>,
+}
+
+impl<'s, 'de, I> BuilderMapAccess<'s, 'de, I>
+where
+    I: Iterator<Item = (BuilderDataType<'de>, BuilderDataType<'de>)>,
+{
+    /// Creates a map access over `data` that can be driven manually with `next_entry_seed`.
+    pub fn new(closure: &'s mut Closure<'de>, data: I) -> Self {
+        let size_hint = match data.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower),
+            _ => None,
+        };
+        BuilderMapAccess {
+            closure,
+            data,
+            leftover: None,
+            size_hint,
+            seen: BTreeSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the next entry, expanding `Spread` keys into the entries of their map.
+    fn next_raw(
+        &mut self,
+    ) -> Result<Option<(BuilderDataType<'de>, BuilderDataType<'de>)>, BuilderError> {
+        loop {
+            let Some((key, value)) = self.pending.pop().or_else(|| self.data.next()) else {
+                return Ok(None);
+            };
+            let BuilderDataType::Spread(target) = key else {
+                return Ok(Some((key, value)));
+            };
+            let entries = self.closure.spread(*target)?;
+            self.pending.extend(entries.into_iter().rev());
+        }
+    }
 }
 
 impl<'s, 'de, I> MapAccess<'de> for BuilderMapAccess<'s, 'de, I>
@@ -21,16 +85,18 @@ where
     where
         K: DeserializeSeed<'de>,
     {
-        if let Some((a, b)) = self.data.next() {
+        while let Some((a, b)) = self.next_raw()? {
+            let Some(a) = self.closure.admit_key(&mut self.seen, a)? else {
+                continue;
+            };
             self.leftover = Some(b);
             let v = seed.deserialize(BuilderDeserializer {
                 closure: self.closure,
                 data: a,
             })?;
-            Ok(Some(v))
-        } else {
-            Ok(None)
+            return Ok(Some(v));
         }
+        Ok(None)
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
@@ -60,7 +126,10 @@ where
         K: DeserializeSeed<'de>,
         V: DeserializeSeed<'de>,
     {
-        if let Some((a, b)) = self.data.next() {
+        while let Some((a, b)) = self.next_raw()? {
+            let Some(a) = self.closure.admit_key(&mut self.seen, a)? else {
+                continue;
+            };
             self.leftover = None;
             let va = kseed.deserialize(BuilderDeserializer {
                 closure: self.closure,
@@ -70,9 +139,8 @@ where
                 closure: self.closure,
                 data: b,
             })?;
-            Ok(Some((va, vb)))
-        } else {
-            Ok(None)
+            return Ok(Some((va, vb)));
         }
+        Ok(None)
     }
 }
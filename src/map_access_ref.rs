@@ -1,4 +1,8 @@
-use crate::{BuilderDataType, BuilderDeserializerRef, BuilderError, Closure};
+use crate::{BuilderDataType, BuilderDeserializer, BuilderDeserializerRef, BuilderError, Closure};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
 use serde::de::{DeserializeSeed, MapAccess};
 
 pub struct BuilderMapAccessRef<'s, 'r, 'de, I>
@@ -8,8 +12,68 @@ where
 {
     pub(crate) closure: &'s mut Closure<'de>,
     pub(crate) data: I,
-    pub(crate) leftover: Option<&'r BuilderDataType<'de>>,
+    pub(crate) leftover: Option<Cow<'r, BuilderDataType<'de>>>,
     pub(crate) size_hint: Option<usize>,
+    pub(crate) seen: BTreeSet<String>,
+    pub(crate) pending: Vec<(BuilderDataType<'de>, BuilderDataType<'de>)>,
+}
+
+type Entry<'r, 'de> = (Cow<'r, BuilderDataType<'de>>, Cow<'r, BuilderDataType<'de>>);
+
+impl<'s, 'r, 'de, I> BuilderMapAccessRef<'s, 'r, 'de, I>
+where
+    I: Iterator<Item = &'r (BuilderDataType<'de>, BuilderDataType<'de>)>,
+{
+    /// Returns the next entry, expanding `Spread` keys into owned copies of their map entries.
+    fn next_raw(&mut self) -> Result<Option<Entry<'r, 'de>>, BuilderError> {
+        loop {
+            let (key, value) = match self.pending.pop() {
+                Some((key, value)) => (Cow::Owned(key), Cow::Owned(value)),
+                None => match self.data.next() {
+                    Some((key, value)) => (Cow::Borrowed(key), Cow::Borrowed(value)),
+                    None => return Ok(None),
+                },
+            };
+            let BuilderDataType::Spread(target) = key.as_ref() else {
+                return Ok(Some((key, value)));
+            };
+            let entries = self.closure.spread(target.as_ref().clone())?;
+            self.pending.extend(entries.into_iter().rev());
+        }
+    }
+
+    fn admit_key(
+        &mut self,
+        key: Cow<'r, BuilderDataType<'de>>,
+    ) -> Result<Option<Cow<'r, BuilderDataType<'de>>>, BuilderError> {
+        if self.closure.duplicate_keys.yields_all() {
+            return Ok(Some(key));
+        }
+        Ok(self
+            .closure
+            .admit_key(&mut self.seen, key.into_owned())?
+            .map(Cow::Owned))
+    }
+
+    fn deserialize<T>(
+        &mut self,
+        seed: T,
+        data: Cow<'r, BuilderDataType<'de>>,
+    ) -> Result<T::Value, BuilderError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match data {
+            Cow::Borrowed(data) => seed.deserialize(BuilderDeserializerRef {
+                closure: self.closure,
+                data,
+            }),
+            Cow::Owned(data) => seed.deserialize(BuilderDeserializer {
+                closure: self.closure,
+                data,
+            }),
+        }
+    }
 }
 
 impl<'s, 'r, 'de, I> MapAccess<'de> for BuilderMapAccessRef<'s, 'r, 'de, I>
@@ -22,16 +86,15 @@ where
     where
         K: DeserializeSeed<'de>,
     {
-        if let Some((a, b)) = self.data.next() {
+        while let Some((a, b)) = self.next_raw()? {
+            let Some(a) = self.admit_key(a)? else {
+                continue;
+            };
             self.leftover = Some(b);
-            let v = seed.deserialize(BuilderDeserializerRef {
-                closure: self.closure,
-                data: a,
-            })?;
-            Ok(Some(v))
-        } else {
-            Ok(None)
+            let v = self.deserialize(seed, a)?;
+            return Ok(Some(v));
         }
+        Ok(None)
     }
 
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
@@ -39,10 +102,7 @@ where
         V: DeserializeSeed<'de>,
     {
         if let Some(leftover) = self.leftover.take() {
-            seed.deserialize(BuilderDeserializerRef {
-                closure: self.closure,
-                data: leftover,
-            })
+            self.deserialize(seed, leftover)
         } else {
             Err(BuilderError::InvalidMapAccess)
         }
@@ -61,19 +121,15 @@ where
         K: DeserializeSeed<'de>,
         V: DeserializeSeed<'de>,
     {
-        if let Some((a, b)) = self.data.next() {
+        while let Some((a, b)) = self.next_raw()? {
+            let Some(a) = self.admit_key(a)? else {
+                continue;
+            };
             self.leftover = None;
-            let va = kseed.deserialize(BuilderDeserializerRef {
-                closure: self.closure,
-                data: a,
-            })?;
-            let vb = vseed.deserialize(BuilderDeserializerRef {
-                closure: self.closure,
-                data: b,
-            })?;
-            Ok(Some((va, vb)))
-        } else {
-            Ok(None)
+            let va = self.deserialize(kseed, a)?;
+            let vb = self.deserialize(vseed, b)?;
+            return Ok(Some((va, vb)));
         }
+        Ok(None)
     }
 }
@@ -0,0 +1,167 @@
+use crate::BuilderDataType;
+use alloc::borrow::ToOwned;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+const WIDTH: usize = 80;
+
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn node(head: &str, items: impl IntoIterator<Item = Sexp>) -> Sexp {
+        Sexp::List(
+            core::iter::once(Sexp::Atom(head.to_owned()))
+                .chain(items)
+                .collect(),
+        )
+    }
+
+    fn inline(&self) -> String {
+        match self {
+            Sexp::Atom(a) => a.clone(),
+            Sexp::List(items) => {
+                let items: Vec<String> = items.iter().map(Sexp::inline).collect();
+                format!("({})", items.join(" "))
+            }
+        }
+    }
+
+    fn render(&self, out: &mut String, indent: usize) {
+        let inline = self.inline();
+        match self {
+            Sexp::List(items) if indent + inline.len() > WIDTH && items.len() > 1 => {
+                out.push('(');
+                items[0].render(out, indent + 1);
+                for item in &items[1..] {
+                    out.push('\n');
+                    out.extend(core::iter::repeat_n(' ', indent + 2));
+                    item.render(out, indent + 2);
+                }
+                out.push(')');
+            }
+            _ => out.push_str(&inline),
+        }
+    }
+}
+
+/// Turns a variant name such as `IfThenElse` into `if-then-else`.
+fn kebab(kind: &str) -> String {
+    let mut out = String::new();
+    for c in kind.chars() {
+        if c.is_uppercase() && !out.is_empty() {
+            out.push('-');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+impl<'de> BuilderDataType<'de> {
+    /// Renders the graph as s-expressions, e.g. `(repeat 3 (list 123 true "test"))`.
+    ///
+    /// Expressions longer than a line are split with their operands indented below the head.
+    /// `Reference` is rendered as its target and `SelfReference` as `<self>`, so cyclic graphs
+    /// print in finite space.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.to_sexp().render(&mut out, 0);
+        out
+    }
+
+    fn to_sexp(&self) -> Sexp {
+        match self {
+            BuilderDataType::Empty => Sexp::Atom("()".to_owned()),
+            BuilderDataType::Boolean(v) => Sexp::Atom(v.to_string()),
+            BuilderDataType::Integer(v) => Sexp::Atom(v.to_string()),
+            BuilderDataType::Unsigned(v) => Sexp::Atom(v.to_string()),
+            BuilderDataType::Number(v) => Sexp::Atom(format!("{v:?}")),
+            BuilderDataType::Float32(v) => Sexp::Atom(format!("{v:?}f32")),
+            BuilderDataType::String(v) => Sexp::Atom(format!("{v:?}")),
+            BuilderDataType::Bytes(v) => Sexp::Atom(format!("b\"{}\"", v.escape_ascii())),
+            BuilderDataType::Map(v) => Sexp::node(
+                "map",
+                v.iter()
+                    .map(|(k, e)| Sexp::List(vec![k.to_sexp(), e.to_sexp()])),
+            ),
+            BuilderDataType::Argument(a) | BuilderDataType::TakeFromArgument(a) => {
+                Sexp::node(&kebab(self.kind()), [Sexp::Atom(a.to_string())])
+            }
+            BuilderDataType::Apply(body, v) => Sexp::node(
+                "apply",
+                core::iter::once(body.to_sexp()).chain(v.iter().map(|e| e.to_sexp())),
+            ),
+            BuilderDataType::ArgumentOr(a, d) => {
+                Sexp::node("argument-or", [Sexp::Atom(a.to_string()), d.to_sexp()])
+            }
+            BuilderDataType::Reference(r) => r.to_sexp(),
+            BuilderDataType::SelfReference(_) => Sexp::Atom("<self>".to_owned()),
+            BuilderDataType::SelfReferenceOr(_, n) => Sexp::node(
+                "self-reference-or",
+                [Sexp::Atom("<self>".to_owned()), n.to_sexp()],
+            ),
+            BuilderDataType::Toggle(t) => {
+                Sexp::node("toggle", [Sexp::Atom(t.borrow().to_string())])
+            }
+            BuilderDataType::Store(r) | BuilderDataType::Take(r) => Sexp::node(
+                &kebab(self.kind()),
+                [match r.try_borrow() {
+                    Ok(r) => r.to_sexp(),
+                    Err(_) => Sexp::Atom("<borrowed>".to_owned()),
+                }],
+            ),
+            BuilderDataType::List(v)
+            | BuilderDataType::Closure(v)
+            | BuilderDataType::IfThenElse(v)
+            | BuilderDataType::Repeat(v)
+            | BuilderDataType::RepeatMap(v)
+            | BuilderDataType::Unfold(v)
+            | BuilderDataType::Interleave(v)
+            | BuilderDataType::Range(v)
+            | BuilderDataType::Sum(v)
+            | BuilderDataType::Multiply(v)
+            | BuilderDataType::Pad(v)
+            | BuilderDataType::Let(v)
+            | BuilderDataType::DefineFn(v)
+            | BuilderDataType::CallFn(v)
+            | BuilderDataType::Switch(v)
+            | BuilderDataType::Weighted(v)
+            | BuilderDataType::While(v)
+            | BuilderDataType::Scan(v)
+            | BuilderDataType::Partition(v)
+            | BuilderDataType::Assert(v)
+            | BuilderDataType::Divide(v)
+            | BuilderDataType::Try(v)
+            | BuilderDataType::ParseInt(v)
+            | BuilderDataType::ParseFloat(v)
+            | BuilderDataType::Tap(v)
+            | BuilderDataType::Quote(v)
+            | BuilderDataType::Replace(v)
+            | BuilderDataType::Variant(v)
+            | BuilderDataType::Path(v) => {
+                Sexp::node(&kebab(self.kind()), v.iter().map(|e| e.to_sexp()))
+            }
+            BuilderDataType::Var(n) => Sexp::node("var", [n.to_sexp()]),
+            BuilderDataType::Spread(n) => Sexp::node("spread", [n.to_sexp()]),
+            BuilderDataType::Lazy(n) => Sexp::node("lazy", [n.to_sexp()]),
+            BuilderDataType::Shuffle(n) => Sexp::node("shuffle", [n.to_sexp()]),
+            BuilderDataType::Comment(n, text) => {
+                Sexp::node("comment", [Sexp::Atom(format!("{text:?}")), n.to_sexp()])
+            }
+            #[cfg(feature = "std-env")]
+            BuilderDataType::Env(n) => Sexp::node("env", [n.to_sexp()]),
+            BuilderDataType::Duration(v) => Sexp::node("duration", v.iter().map(|e| e.to_sexp())),
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) | BuilderDataType::Capture(v) => {
+                Sexp::node(&kebab(self.kind()), v.iter().map(|e| e.to_sexp()))
+            }
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => {
+                Sexp::node("format-date", v.iter().map(|e| e.to_sexp()))
+            }
+            _ => Sexp::Atom(kebab(self.kind())),
+        }
+    }
+}
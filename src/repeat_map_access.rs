@@ -0,0 +1,62 @@
+use crate::closure::SIZE_HINT_LIMIT;
+use crate::{BuilderDataType, BuilderDeserializerRef, BuilderError, Closure};
+use serde::de::{DeserializeSeed, MapAccess};
+
+pub struct BuilderRepeatMapAccess<'s, 'r, 'de>
+where
+    'de: 'r,
+{
+    pub(crate) closure: &'s mut Closure<'de>,
+    pub(crate) key: &'r BuilderDataType<'de>,
+    pub(crate) value: &'r BuilderDataType<'de>,
+    pub(crate) count: usize,
+    pub(crate) index: usize,
+}
+
+impl<'s, 'r, 'de> BuilderRepeatMapAccess<'s, 'r, 'de> {
+    /// Deserializes `data` with `Index` set to the current entry.
+    fn deserialize<T>(
+        &mut self,
+        seed: T,
+        data: &'r BuilderDataType<'de>,
+    ) -> Result<T::Value, BuilderError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let outer = self.closure.index;
+        self.closure.index = self.index;
+        let value = seed.deserialize(BuilderDeserializerRef {
+            closure: self.closure,
+            data,
+        });
+        self.closure.index = outer;
+        value
+    }
+}
+
+impl<'s, 'r, 'de> MapAccess<'de> for BuilderRepeatMapAccess<'s, 'r, 'de> {
+    type Error = BuilderError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.index >= self.count {
+            return Ok(None);
+        }
+        self.deserialize(seed, self.key).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self.deserialize(seed, self.value);
+        self.index += 1;
+        value
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.count.saturating_sub(self.index).min(SIZE_HINT_LIMIT))
+    }
+}
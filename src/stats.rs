@@ -0,0 +1,25 @@
+use alloc::collections::BTreeMap;
+
+/// Counters collected by `from_data_with_stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// Number of nodes passed to `deserialize_any`, by variant name.
+    pub visits: BTreeMap<&'static str, usize>,
+    /// Deepest nesting of node deserializations.
+    pub max_depth: usize,
+    /// Number of nodes copied out of the graph to be resolved or passed as arguments.
+    pub clones: usize,
+    depth: usize,
+}
+
+impl Stats {
+    pub(crate) fn enter(&mut self, kind: &'static str) {
+        *self.visits.entry(kind).or_default() += 1;
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{BuilderDataType, BuilderDeserializerRef, BuilderError, Closure};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use serde::Deserialize;
+
+/// A graph that can be deserialized many times with identical results.
+///
+/// `Take` drains the value held by its cell, so a plain graph changes every time it is
+/// deserialized, as does `Toggle` with its flag. A `Template` records the initial value of every
+/// `Store`/`Take` cell and `Toggle` flag when it is created and puts those values back before
+/// each run.
+pub struct Template<'de> {
+    data: BuilderDataType<'de>,
+    stores: Vec<(Rc<RefCell<BuilderDataType<'de>>>, BuilderDataType<'de>)>,
+    toggles: Vec<(Rc<RefCell<bool>>, bool)>,
+}
+
+impl<'de> Template<'de> {
+    pub fn new(data: BuilderDataType<'de>) -> Self {
+        let mut stores: Vec<(Rc<RefCell<BuilderDataType<'de>>>, BuilderDataType<'de>)> = Vec::new();
+        let mut toggles: Vec<(Rc<RefCell<bool>>, bool)> = Vec::new();
+        data.walk(&mut |b| match b {
+            BuilderDataType::Store(r) | BuilderDataType::Take(r)
+                if !stores.iter().any(|(s, _)| Rc::ptr_eq(s, r)) =>
+            {
+                stores.push((r.clone(), r.as_ref().borrow().clone()));
+            }
+            BuilderDataType::Toggle(t) if !toggles.iter().any(|(s, _)| Rc::ptr_eq(s, t)) => {
+                toggles.push((t.clone(), *t.borrow()));
+            }
+            _ => {}
+        });
+        Template {
+            data,
+            stores,
+            toggles,
+        }
+    }
+
+    /// Restores every `Store`/`Take` cell and `Toggle` flag to the value it held when the
+    /// template was created.
+    pub fn reset_stores(&self) {
+        for (cell, initial) in &self.stores {
+            *cell.as_ref().borrow_mut() = initial.clone();
+        }
+        for (flag, initial) in &self.toggles {
+            *flag.borrow_mut() = *initial;
+        }
+    }
+
+    pub fn deserialize<T>(&self) -> Result<T, BuilderError>
+    where
+        T: Deserialize<'de>,
+    {
+        self.deserialize_with(&mut Closure::new())
+    }
+
+    /// Like `deserialize`, but runs in `closure`, which can be reused across runs. Each run
+    /// starts with `Index` back at 0.
+    pub fn deserialize_with<T>(&self, closure: &mut Closure<'de>) -> Result<T, BuilderError>
+    where
+        T: Deserialize<'de>,
+    {
+        self.reset_stores();
+        closure.index = 0;
+        T::deserialize(BuilderDeserializerRef {
+            closure,
+            data: &self.data,
+        })
+    }
+}
@@ -1,9 +1,9 @@
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 struct TestSimple {
@@ -175,3 +175,3435 @@ fn test_closure() {
 
     assert_eq!(fixture_data_complex(3), test);
 }
+
+#[cfg(feature = "std-time")]
+#[test]
+fn test_now_with_fixed_clock() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Record {
+        id: u32,
+        created: u64,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("id")),
+            BuilderDataType::Integer(7),
+        ),
+        (
+            BuilderDataType::String(Cow::from("created")),
+            BuilderDataType::Now,
+        ),
+    ]);
+
+    let test: Record = from_data_with_clock(data, || 1_700_000_000).unwrap();
+
+    assert_eq!(
+        Record {
+            id: 7,
+            created: 1_700_000_000
+        },
+        test
+    );
+}
+
+#[cfg(feature = "std-env")]
+#[test]
+fn test_env_with_injected_map() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Settings {
+        home: String,
+        editor: Option<String>,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("home")),
+            BuilderDataType::Env(Box::new(BuilderDataType::String(Cow::from("HOME")))),
+        ),
+        (
+            BuilderDataType::String(Cow::from("editor")),
+            BuilderDataType::Env(Box::new(BuilderDataType::String(Cow::from("EDITOR")))),
+        ),
+    ]);
+    let env = std::collections::HashMap::from([("HOME".to_owned(), "/home/test".to_owned())]);
+
+    let test: Settings = from_data_with_env(data, env).unwrap();
+
+    assert_eq!(
+        Settings {
+            home: "/home/test".to_owned(),
+            editor: None
+        },
+        test
+    );
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct TestFlags {
+    a: bool,
+    b: bool,
+    c: bool,
+}
+
+fn fixture_flags() -> BuilderDataType<'static> {
+    BuilderDataType::List(vec![
+        BuilderDataType::String(Cow::from("TRUE")),
+        BuilderDataType::Integer(2),
+        BuilderDataType::String(Cow::from("false")),
+    ])
+}
+
+#[test]
+fn test_bool_coercion() {
+    let test: TestFlags = from_data(fixture_flags()).unwrap();
+    assert_eq!(
+        TestFlags {
+            a: true,
+            b: true,
+            c: false
+        },
+        test
+    );
+
+    let test: TestFlags = from_ref(&fixture_flags()).unwrap();
+    assert_eq!(
+        TestFlags {
+            a: true,
+            b: true,
+            c: false
+        },
+        test
+    );
+}
+
+#[test]
+fn test_bool_strict() {
+    assert!(from_data_strict::<TestFlags>(fixture_flags()).is_err());
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Boolean(true),
+        BuilderDataType::Boolean(false),
+        BuilderDataType::Boolean(true),
+    ]);
+    let test: TestFlags = from_data_strict(data).unwrap();
+    assert_eq!(
+        TestFlags {
+            a: true,
+            b: false,
+            c: true
+        },
+        test
+    );
+}
+
+#[test]
+fn test_pad() {
+    let data = BuilderDataType::Pad(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::Integer(1),
+            BuilderDataType::Integer(2),
+        ]),
+        BuilderDataType::Unsigned(4),
+        BuilderDataType::Integer(0),
+    ]);
+
+    let test: [i32; 4] = from_ref(&data).unwrap();
+    assert_eq!([1, 2, 0, 0], test);
+
+    let test: [i32; 4] = from_data(data).unwrap();
+    assert_eq!([1, 2, 0, 0], test);
+
+    let data = BuilderDataType::Pad(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::Integer(1),
+            BuilderDataType::Integer(2),
+            BuilderDataType::Integer(3),
+        ]),
+        BuilderDataType::Unsigned(2),
+        BuilderDataType::Integer(0),
+    ]);
+
+    let test: [i32; 2] = from_data(data).unwrap();
+    assert_eq!([1, 2], test);
+}
+
+#[test]
+fn test_tuple_struct_length_mismatch() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Rgb(u8, u8, u8);
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Unsigned(1),
+        BuilderDataType::Unsigned(2),
+        BuilderDataType::Unsigned(3),
+    ]);
+    let test: Rgb = from_ref(&data).unwrap();
+    assert_eq!(Rgb(1, 2, 3), test);
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Unsigned(1),
+        BuilderDataType::Unsigned(2),
+        BuilderDataType::Unsigned(3),
+        BuilderDataType::Unsigned(4),
+    ]);
+    let err = from_ref::<Rgb>(&data).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("expected tuple struct Rgb of length 3"));
+    let err = from_data::<Rgb>(data).unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("expected tuple struct Rgb of length 3"));
+}
+
+#[test]
+fn test_template_resets_stores() {
+    let nest_count = Rc::new(RefCell::new(BuilderDataType::Integer(3)));
+
+    let data = Rc::new_cyclic(|self_reference| {
+        BuilderDataType::List(vec![
+            BuilderDataType::Repeat(vec![
+                BuilderDataType::Unsigned(3),
+                BuilderDataType::List(vec![
+                    BuilderDataType::Integer(123),
+                    BuilderDataType::Boolean(true),
+                    BuilderDataType::String(Cow::from("test")),
+                ]),
+            ]),
+            BuilderDataType::IfThenElse(vec![
+                BuilderDataType::Take(nest_count.clone()),
+                BuilderDataType::Map(vec![(
+                    BuilderDataType::String(Cow::from("test")),
+                    BuilderDataType::SelfReference(self_reference.clone()),
+                )]),
+                BuilderDataType::Map(vec![]),
+            ]),
+        ])
+    });
+
+    let template = Template::new(BuilderDataType::Reference(data));
+
+    let first: TestComplex = template.deserialize().unwrap();
+    let second: TestComplex = template.deserialize().unwrap();
+
+    assert_eq!(fixture_data_complex(3), first);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn test_template_resets_index() {
+    let template = Template::new(BuilderDataType::Index);
+    let mut closure = Closure::new();
+    closure.index = 5;
+
+    let first: u64 = template.deserialize_with(&mut closure).unwrap();
+    closure.index = 5;
+    let second: u64 = template.deserialize_with(&mut closure).unwrap();
+
+    assert_eq!(0, first);
+    assert_eq!(0, second);
+}
+
+#[test]
+fn test_let_binding() {
+    let counter = Rc::new(RefCell::new(BuilderDataType::Integer(3)));
+    let data = BuilderDataType::Let(vec![
+        BuilderDataType::String(Cow::from("n")),
+        BuilderDataType::Take(counter),
+        BuilderDataType::List(vec![
+            BuilderDataType::Var(Box::new(BuilderDataType::String(Cow::from("n")))),
+            BuilderDataType::Var(Box::new(BuilderDataType::String(Cow::from("n")))),
+        ]),
+    ]);
+
+    let test: (i32, i32) = from_ref(&data).unwrap();
+    assert_eq!((3, 3), test);
+
+    let test: (i32, i32) = from_data(data).unwrap();
+    assert_eq!((2, 2), test);
+
+    let data = BuilderDataType::Var(Box::new(BuilderDataType::String(Cow::from("n"))));
+    assert!(matches!(
+        from_data::<i32>(data),
+        Err(BuilderError::UnboundVariable(name)) if name == "n"
+    ));
+}
+
+#[test]
+fn test_switch() {
+    let switch = |selector: &'static str| {
+        BuilderDataType::Switch(vec![
+            BuilderDataType::String(Cow::from(selector)),
+            BuilderDataType::String(Cow::from("red")),
+            BuilderDataType::Integer(1),
+            BuilderDataType::String(Cow::from("green")),
+            BuilderDataType::Integer(2),
+            BuilderDataType::String(Cow::from("blue")),
+            BuilderDataType::Integer(3),
+            BuilderDataType::Integer(0),
+        ])
+    };
+    let data = BuilderDataType::List(vec![
+        switch("red"),
+        switch("green"),
+        switch("blue"),
+        switch("purple"),
+    ]);
+
+    let test: Vec<i32> = from_ref(&data).unwrap();
+    assert_eq!(vec![1, 2, 3, 0], test);
+
+    let test: Vec<i32> = from_data(data).unwrap();
+    assert_eq!(vec![1, 2, 3, 0], test);
+}
+
+#[test]
+fn test_while() {
+    let counter = Rc::new(RefCell::new(BuilderDataType::Integer(3)));
+    let data = BuilderDataType::While(vec![
+        BuilderDataType::Take(counter.clone()),
+        BuilderDataType::Index,
+    ]);
+
+    let test: Vec<u32> = from_ref(&data).unwrap();
+    assert_eq!(vec![0, 1, 2], test);
+
+    *counter.borrow_mut() = BuilderDataType::Integer(3);
+    let test: Vec<u32> = from_data(data).unwrap();
+    assert_eq!(vec![0, 1, 2], test);
+
+    let data = BuilderDataType::While(vec![
+        BuilderDataType::Boolean(true),
+        BuilderDataType::Integer(1),
+    ]);
+    assert!(matches!(
+        from_data::<Vec<u32>>(data),
+        Err(BuilderError::IterationLimit(_))
+    ));
+}
+
+#[test]
+fn test_scan() {
+    let data = BuilderDataType::Scan(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::Integer(1),
+            BuilderDataType::Integer(2),
+            BuilderDataType::Integer(3),
+        ]),
+        BuilderDataType::Integer(0),
+        BuilderDataType::Sum(vec![
+            BuilderDataType::Argument(0),
+            BuilderDataType::Argument(1),
+        ]),
+    ]);
+
+    let test: Vec<i32> = from_ref(&data).unwrap();
+    assert_eq!(vec![1, 3, 6], test);
+
+    let test: Vec<i32> = from_data(data).unwrap();
+    assert_eq!(vec![1, 3, 6], test);
+}
+
+#[test]
+fn test_error_clone_and_compare() {
+    let pad = |list| {
+        BuilderDataType::Pad(vec![
+            list,
+            BuilderDataType::Unsigned(2),
+            BuilderDataType::Integer(0),
+        ])
+    };
+
+    let first = from_data::<Vec<i32>>(pad(BuilderDataType::Integer(1))).unwrap_err();
+    let second = from_data::<Vec<i32>>(pad(BuilderDataType::Boolean(true))).unwrap_err();
+
+    assert_eq!(
+        BuilderError::TypeMismatch {
+            expected: "List",
+            found: "Integer"
+        },
+        first
+    );
+    assert_eq!(first.clone(), first);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_unsupported_variant_errors() {
+    let data = BuilderDataType::List(vec![BuilderDataType::Unique]);
+
+    assert_eq!(
+        Err(BuilderError::UnsupportedVariant("Unique")),
+        from_ref::<Vec<u32>>(&data)
+    );
+    assert_eq!(
+        Err(BuilderError::UnsupportedVariant("Unique")),
+        from_data::<Vec<u32>>(data)
+    );
+}
+
+#[test]
+fn test_pretty() {
+    let data = BuilderDataType::Repeat(vec![
+        BuilderDataType::Unsigned(3),
+        BuilderDataType::List(vec![
+            BuilderDataType::Integer(123),
+            BuilderDataType::Boolean(true),
+            BuilderDataType::String(Cow::from("test")),
+        ]),
+    ]);
+    assert_eq!(r#"(repeat 3 (list 123 true "test"))"#, data.pretty());
+
+    let data = Rc::new_cyclic(|self_reference| {
+        BuilderDataType::IfThenElse(vec![
+            BuilderDataType::Take(Rc::new(RefCell::new(BuilderDataType::Integer(3)))),
+            BuilderDataType::Map(vec![(
+                BuilderDataType::String(Cow::from(
+                    "a_long_key_that_pushes_the_expression_past_one_line",
+                )),
+                BuilderDataType::SelfReference(self_reference.clone()),
+            )]),
+            BuilderDataType::Map(vec![]),
+        ])
+    });
+    assert_eq!(
+        r#"(if-then-else
+  (take 3)
+  (map ("a_long_key_that_pushes_the_expression_past_one_line" <self>))
+  (map))"#,
+        BuilderDataType::Reference(data).pretty()
+    );
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+enum TestColor {
+    Red,
+    Green,
+    Blue,
+}
+
+#[test]
+fn test_enum_variant_by_index() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Unsigned(1),
+        BuilderDataType::Integer(2),
+        BuilderDataType::String(Cow::from("Red")),
+    ]);
+
+    let test: Vec<TestColor> = from_ref(&data).unwrap();
+    assert_eq!(
+        vec![TestColor::Green, TestColor::Blue, TestColor::Red],
+        test
+    );
+
+    let test: Vec<TestColor> = from_data(data).unwrap();
+    assert_eq!(
+        vec![TestColor::Green, TestColor::Blue, TestColor::Red],
+        test
+    );
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Shape {
+        Point,
+        Circle(u32),
+    }
+    let data = BuilderDataType::Map(vec![(
+        BuilderDataType::Unsigned(1),
+        BuilderDataType::Integer(5),
+    )]);
+    let test: Shape = from_data(data).unwrap();
+    assert_eq!(Shape::Circle(5), test);
+    assert!(from_data::<Shape>(BuilderDataType::Integer(7)).is_err());
+}
+
+#[test]
+fn test_assert() {
+    let assert_non_empty = |list| {
+        BuilderDataType::Assert(vec![
+            BuilderDataType::Reference(Rc::clone(&list)),
+            BuilderDataType::Reference(list),
+            BuilderDataType::String(Cow::from("generated list must not be empty")),
+        ])
+    };
+
+    let data = assert_non_empty(Rc::new(BuilderDataType::List(vec![
+        BuilderDataType::Integer(1),
+    ])));
+    let test: Vec<i32> = from_ref(&data).unwrap();
+    assert_eq!(vec![1], test);
+
+    let data = assert_non_empty(Rc::new(BuilderDataType::List(vec![])));
+    assert_eq!(
+        Err(BuilderError::AssertionFailed(
+            "generated list must not be empty".to_owned()
+        )),
+        from_data::<Vec<i32>>(data)
+    );
+}
+
+#[test]
+fn test_try_falls_back_on_error() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Try(vec![
+            BuilderDataType::Divide(vec![
+                BuilderDataType::Integer(10),
+                BuilderDataType::Integer(0),
+            ]),
+            BuilderDataType::Integer(-1),
+        ]),
+        BuilderDataType::Try(vec![
+            BuilderDataType::Divide(vec![
+                BuilderDataType::Integer(10),
+                BuilderDataType::Integer(2),
+            ]),
+            BuilderDataType::Integer(-1),
+        ]),
+    ]);
+
+    let test: Vec<i32> = from_ref(&data).unwrap();
+    assert_eq!(vec![-1, 5], test);
+
+    let test: Vec<i32> = from_data(data).unwrap();
+    assert_eq!(vec![-1, 5], test);
+
+    let data = BuilderDataType::Divide(vec![
+        BuilderDataType::Integer(10),
+        BuilderDataType::Integer(0),
+    ]);
+    assert_eq!(Err(BuilderError::DivisionByZero), from_data::<i32>(data));
+}
+
+#[test]
+fn test_index_in_nested_repeat() {
+    let data = BuilderDataType::Repeat(vec![
+        BuilderDataType::Unsigned(2),
+        BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(3), BuilderDataType::Index]),
+    ]);
+
+    let test: Vec<Vec<u32>> = from_ref(&data).unwrap();
+    assert_eq!(vec![vec![0, 1, 2], vec![0, 1, 2]], test);
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Index,
+        BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(3), BuilderDataType::Index]),
+        BuilderDataType::Index,
+    ]);
+
+    let test: (u32, Vec<u32>, u32) = from_data(data).unwrap();
+    assert_eq!((0, vec![0, 1, 2], 2), test);
+}
+
+#[test]
+fn test_repeat_body_uses_index() {
+    let data = BuilderDataType::Repeat(vec![
+        BuilderDataType::Unsigned(4),
+        BuilderDataType::Multiply(vec![BuilderDataType::Index, BuilderDataType::Integer(2)]),
+    ]);
+
+    assert_eq!(Ok(vec![0, 2, 4, 6]), from_ref::<Vec<i32>>(&data));
+    assert_eq!(Ok(vec![0, 2, 4, 6]), from_data::<Vec<i32>>(data));
+}
+
+#[test]
+fn test_index_outside_sequence_is_zero() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Indexed {
+        a: Vec<u32>,
+        b: u32,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(3), BuilderDataType::Index]),
+        ),
+        (
+            BuilderDataType::String(Cow::from("b")),
+            BuilderDataType::Index,
+        ),
+    ]);
+
+    let test: Indexed = from_data(data).unwrap();
+    assert_eq!(
+        Indexed {
+            a: vec![0, 1, 2],
+            b: 0
+        },
+        test
+    );
+    assert_eq!(0, from_data::<u32>(BuilderDataType::Index).unwrap());
+}
+
+#[test]
+fn test_index_in_closure_body() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Integer(7),
+        BuilderDataType::Closure(vec![BuilderDataType::Index]),
+        BuilderDataType::Closure(vec![
+            BuilderDataType::List(vec![BuilderDataType::Index, BuilderDataType::Argument(1)]),
+            BuilderDataType::Index,
+        ]),
+    ]);
+
+    let test: (u32, u32, (u32, u32)) = from_data(data).unwrap();
+    assert_eq!((7, 1, (0, 1)), test);
+}
+
+#[test]
+fn test_bytes_conversions() {
+    let data = BuilderDataType::Bytes(Cow::Borrowed(b"hello"));
+
+    assert_eq!("hello", data.to_string());
+    assert!(matches!(data.to_string(), Cow::Borrowed(_)));
+    assert_eq!(5, data.to_unsigned());
+    assert_eq!(5, data.to_signed());
+    assert_eq!(5.0, data.to_float());
+    assert!(data.check_true());
+
+    let data = BuilderDataType::Bytes(Cow::Owned(vec![b'a', 0xff, b'b']));
+    assert_eq!("a\u{fffd}b", data.to_string());
+    assert_eq!(3, data.to_unsigned());
+
+    let data = BuilderDataType::Bytes(Cow::Owned(Vec::new()));
+    assert_eq!("", data.to_string());
+    assert_eq!(0, data.to_signed());
+    assert!(!data.check_true());
+}
+
+#[test]
+fn test_argument_or_default() {
+    let data = BuilderDataType::Closure(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::ArgumentOr(1, Box::new(BuilderDataType::Integer(0))),
+            BuilderDataType::ArgumentOr(
+                2,
+                Box::new(BuilderDataType::Sum(vec![
+                    BuilderDataType::Integer(40),
+                    BuilderDataType::Integer(2),
+                ])),
+            ),
+        ]),
+        BuilderDataType::Integer(7),
+    ]);
+
+    let test: (i64, i64) = from_ref(&data).unwrap();
+    assert_eq!((7, 42), test);
+
+    let test: (i64, i64) = from_data(data).unwrap();
+    assert_eq!((7, 42), test);
+}
+
+#[test]
+fn test_manual_map_access() {
+    use serde::de::MapAccess;
+    use std::marker::PhantomData;
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("first")),
+            BuilderDataType::Integer(1),
+        ),
+        (
+            BuilderDataType::String(Cow::from("second")),
+            BuilderDataType::Sum(vec![
+                BuilderDataType::Integer(1),
+                BuilderDataType::Integer(1),
+            ]),
+        ),
+        (
+            BuilderDataType::String(Cow::from("third")),
+            BuilderDataType::Integer(3),
+        ),
+    ]);
+
+    let mut closure = Closure::new();
+    let mut access = to_map_access(&mut closure, data).unwrap();
+    assert_eq!(Some(3), access.size_hint());
+
+    let first = access
+        .next_entry_seed(PhantomData::<String>, PhantomData::<i64>)
+        .unwrap();
+    assert_eq!(Some(("first".to_owned(), 1)), first);
+    let second = access
+        .next_entry_seed(PhantomData::<String>, PhantomData::<i64>)
+        .unwrap();
+    assert_eq!(Some(("second".to_owned(), 2)), second);
+
+    assert_eq!(
+        Err(BuilderError::TypeMismatch {
+            expected: "Map",
+            found: "List",
+        }),
+        to_map_access(&mut closure, BuilderDataType::List(vec![])).map(|_| ())
+    );
+}
+
+#[test]
+fn test_duplicate_key_policy() {
+    let map = || {
+        BuilderDataType::Map(vec![
+            (
+                BuilderDataType::String(Cow::from("a")),
+                BuilderDataType::Integer(1),
+            ),
+            (
+                BuilderDataType::String(Cow::from("b")),
+                BuilderDataType::Integer(2),
+            ),
+            (
+                BuilderDataType::String(Cow::from("a")),
+                BuilderDataType::Integer(3),
+            ),
+        ])
+    };
+    // `Repeat` walks its body by reference, covering the borrowed map access.
+    let repeated = || BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(1), map()]);
+
+    let last: BTreeMap<String, i64> =
+        from_data_with_duplicate_keys(map(), DuplicateKeyPolicy::Last).unwrap();
+    assert_eq!(
+        BTreeMap::from([("a".to_owned(), 3), ("b".to_owned(), 2)]),
+        last
+    );
+    let last: Vec<BTreeMap<String, i64>> =
+        from_data_with_duplicate_keys(repeated(), DuplicateKeyPolicy::Last).unwrap();
+    assert_eq!(
+        vec![BTreeMap::from([("a".to_owned(), 3), ("b".to_owned(), 2)])],
+        last
+    );
+
+    let first: BTreeMap<String, i64> =
+        from_data_with_duplicate_keys(map(), DuplicateKeyPolicy::First).unwrap();
+    assert_eq!(
+        BTreeMap::from([("a".to_owned(), 1), ("b".to_owned(), 2)]),
+        first
+    );
+    let first: Vec<BTreeMap<String, i64>> =
+        from_data_with_duplicate_keys(repeated(), DuplicateKeyPolicy::First).unwrap();
+    assert_eq!(
+        vec![BTreeMap::from([("a".to_owned(), 1), ("b".to_owned(), 2)])],
+        first
+    );
+
+    let error: Result<BTreeMap<String, i64>, _> =
+        from_data_with_duplicate_keys(map(), DuplicateKeyPolicy::Error);
+    assert_eq!(Err(BuilderError::DuplicateKey("a".to_owned())), error);
+    let error: Result<Vec<BTreeMap<String, i64>>, _> =
+        from_data_with_duplicate_keys(repeated(), DuplicateKeyPolicy::Error);
+    assert_eq!(Err(BuilderError::DuplicateKey("a".to_owned())), error);
+}
+
+#[test]
+fn test_wide_struct_from_map() {
+    macro_rules! wide {
+        ($($field:ident),*) => {
+            #[derive(Debug, Deserialize)]
+            struct Wide {
+                $($field: u32),*
+            }
+
+            impl Wide {
+                fn values(&self) -> Vec<u32> {
+                    vec![$(self.$field),*]
+                }
+            }
+        };
+    }
+    wide!(
+        f0, f1, f2, f3, f4, f5, f6, f7, f8, f9, f10, f11, f12, f13, f14, f15, f16, f17, f18, f19,
+        f20, f21, f22, f23, f24, f25, f26, f27, f28, f29, f30, f31, f32, f33, f34, f35, f36, f37,
+        f38, f39, f40, f41, f42, f43, f44, f45, f46, f47, f48, f49, f50, f51, f52, f53, f54, f55,
+        f56, f57, f58, f59, f60, f61, f62, f63, f64, f65, f66, f67, f68, f69, f70, f71, f72, f73,
+        f74, f75, f76, f77, f78, f79, f80, f81, f82, f83, f84, f85, f86, f87, f88, f89, f90, f91,
+        f92, f93, f94, f95, f96, f97, f98, f99
+    );
+
+    // Entries in reverse declaration order, with an unknown key in between.
+    let mut entries: Vec<_> = (0..100u64)
+        .rev()
+        .map(|i| {
+            (
+                BuilderDataType::String(Cow::Owned(format!("f{i}"))),
+                BuilderDataType::Unsigned(i),
+            )
+        })
+        .collect();
+    entries.insert(
+        50,
+        (
+            BuilderDataType::String(Cow::from("unknown")),
+            BuilderDataType::Boolean(true),
+        ),
+    );
+    let data = BuilderDataType::Map(entries);
+    let expected: Vec<u32> = (0..100).collect();
+
+    let test: Wide = from_ref(&data).unwrap();
+    assert_eq!(expected, test.values());
+
+    let test: Wide = from_data(data).unwrap();
+    assert_eq!(expected, test.values());
+}
+
+#[test]
+fn test_take_from_argument_bounds() {
+    let data = |a| {
+        BuilderDataType::Closure(vec![
+            BuilderDataType::List(vec![
+                BuilderDataType::TakeFromArgument(a),
+                BuilderDataType::TakeFromArgument(a),
+            ]),
+            BuilderDataType::Integer(2),
+        ])
+    };
+
+    let by_ref: Result<(i64, i64), _> = from_ref(&data(1));
+    let owned: Result<(i64, i64), _> = from_data(data(1));
+    assert_eq!(Ok((2, 1)), by_ref);
+    assert_eq!(by_ref, owned);
+
+    let by_ref: Result<(i64, i64), _> = from_ref(&data(5));
+    let owned: Result<(i64, i64), _> = from_data(data(5));
+    assert_eq!(Err(BuilderError::InvalidFunctionArgument), by_ref);
+    assert_eq!(by_ref, owned);
+}
+
+#[test]
+fn test_borrowed_argument_stays_borrowed() {
+    let source = String::from("borrowed");
+    let data = BuilderDataType::Closure(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::Argument(1),
+            BuilderDataType::IfThenElse(vec![
+                BuilderDataType::Boolean(true),
+                BuilderDataType::Argument(1),
+                BuilderDataType::Empty,
+            ]),
+        ]),
+        BuilderDataType::String(Cow::Borrowed(source.as_str())),
+    ]);
+
+    let (a, b): (&str, &str) = from_ref(&data).unwrap();
+    assert_eq!(source.as_ptr(), a.as_ptr());
+    assert_eq!(source.as_ptr(), b.as_ptr());
+
+    let (a, b): (&str, &str) = from_data(data).unwrap();
+    assert_eq!(source.as_ptr(), a.as_ptr());
+    assert_eq!(source.as_ptr(), b.as_ptr());
+}
+
+#[test]
+fn test_enum_map_keys() {
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("Red")),
+            BuilderDataType::Integer(1),
+        ),
+        (BuilderDataType::Unsigned(2), BuilderDataType::Integer(3)),
+    ]);
+    let expected = BTreeMap::from([(TestColor::Red, 1), (TestColor::Blue, 3)]);
+
+    let test: BTreeMap<TestColor, i32> = from_ref(&data).unwrap();
+    assert_eq!(expected, test);
+
+    let test: BTreeMap<TestColor, i32> = from_data(data).unwrap();
+    assert_eq!(expected, test);
+}
+
+#[test]
+fn test_spread_into_map() {
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Integer(1),
+        ),
+        (
+            BuilderDataType::Spread(Box::new(BuilderDataType::Map(vec![(
+                BuilderDataType::String(Cow::from("b")),
+                BuilderDataType::Integer(2),
+            )]))),
+            BuilderDataType::Empty,
+        ),
+        (
+            BuilderDataType::String(Cow::from("c")),
+            BuilderDataType::Integer(3),
+        ),
+    ]);
+    let expected = BTreeMap::from([
+        ("a".to_owned(), 1),
+        ("b".to_owned(), 2),
+        ("c".to_owned(), 3),
+    ]);
+
+    let test: BTreeMap<String, i64> = from_ref(&data).unwrap();
+    assert_eq!(expected, test);
+
+    let test: BTreeMap<String, i64> = from_data(data.clone()).unwrap();
+    assert_eq!(expected, test);
+
+    /// Reads the size hint of a map, then drains it.
+    struct MapHint;
+
+    impl<'de> serde::de::Visitor<'de> for MapHint {
+        type Value = Option<usize>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a map")
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> Result<Self::Value, A::Error> {
+            let hint = map.size_hint();
+            while map
+                .next_entry::<serde::de::IgnoredAny, serde::de::IgnoredAny>()?
+                .is_some()
+            {}
+            Ok(hint)
+        }
+    }
+
+    // A spread key yields an unknown number of entries, so there is no hint.
+    fn hint<'de>(
+        data: &BuilderDataType<'de>,
+        closure: &mut Closure<'de>,
+    ) -> Result<Option<usize>, BuilderError> {
+        serde::Deserializer::deserialize_any(BuilderDeserializerRef { closure, data }, MapHint)
+    }
+    assert_eq!(Ok(None), hint(&data, &mut Closure::new()));
+    assert_eq!(
+        Ok(None),
+        serde::Deserializer::deserialize_any(
+            BuilderDeserializer {
+                closure: &mut Closure::new(),
+                data,
+            },
+            MapHint
+        )
+    );
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Integer(1),
+        ),
+        (
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Integer(2),
+        ),
+    ]);
+    assert_eq!(Ok(Some(2)), hint(&data, &mut Closure::new()));
+    // Skipping repeated keys makes the count unknown too.
+    let mut closure = Closure::new();
+    closure.duplicate_keys = DuplicateKeyPolicy::First;
+    assert_eq!(Ok(None), hint(&data, &mut closure));
+}
+
+#[test]
+fn test_empty_as_empty_collection() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Record {
+        list: Vec<i32>,
+        map: BTreeMap<String, i32>,
+        missing: Option<Vec<i32>>,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("list")),
+            BuilderDataType::Empty,
+        ),
+        (
+            BuilderDataType::String(Cow::from("map")),
+            BuilderDataType::Empty,
+        ),
+        (
+            BuilderDataType::String(Cow::from("missing")),
+            BuilderDataType::Empty,
+        ),
+    ]);
+    let expected = Record {
+        list: vec![],
+        map: BTreeMap::new(),
+        missing: None,
+    };
+
+    let test: Record = from_ref(&data).unwrap();
+    assert_eq!(expected, test);
+
+    let test: Record = from_data(data).unwrap();
+    assert_eq!(expected, test);
+
+    let test: Vec<i32> = from_data(BuilderDataType::Empty).unwrap();
+    assert_eq!(Vec::<i32>::new(), test);
+    let test: BTreeMap<String, i32> = from_ref(&BuilderDataType::Empty).unwrap();
+    assert_eq!(BTreeMap::new(), test);
+}
+
+#[test]
+fn test_parse_numbers() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::ParseInt(vec![
+            BuilderDataType::String(Cow::from("ff")),
+            BuilderDataType::Unsigned(16),
+        ]),
+        BuilderDataType::ParseInt(vec![BuilderDataType::String(Cow::from("-42"))]),
+        BuilderDataType::ParseFloat(vec![BuilderDataType::String(Cow::from("2.5"))]),
+    ]);
+
+    let test: (i64, i64, f64) = from_ref(&data).unwrap();
+    assert_eq!((255, -42, 2.5), test);
+
+    let test: (i64, i64, f64) = from_data(data).unwrap();
+    assert_eq!((255, -42, 2.5), test);
+
+    let data = BuilderDataType::ParseInt(vec![BuilderDataType::String(Cow::from("xyz"))]);
+    assert_eq!(
+        Err(BuilderError::ParseFailed("xyz".to_owned())),
+        from_ref::<i64>(&data)
+    );
+    assert_eq!(
+        Err(BuilderError::ParseFailed("xyz".to_owned())),
+        from_data::<i64>(data)
+    );
+}
+
+#[test]
+fn test_sorted_maps() {
+    struct Entries(Vec<String>);
+
+    impl<'de> Deserialize<'de> for Entries {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct EntriesVisitor;
+
+            impl<'de> serde::de::Visitor<'de> for EntriesVisitor {
+                type Value = Entries;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a map")
+                }
+
+                fn visit_map<A>(self, mut map: A) -> Result<Entries, A::Error>
+                where
+                    A: serde::de::MapAccess<'de>,
+                {
+                    let mut keys = Vec::new();
+                    while let Some((key, _)) = map.next_entry::<String, i64>()? {
+                        keys.push(key);
+                    }
+                    Ok(Entries(keys))
+                }
+            }
+
+            deserializer.deserialize_map(EntriesVisitor)
+        }
+    }
+
+    let data = BuilderDataType::Map(
+        ["c", "a", "b"]
+            .into_iter()
+            .map(|k| {
+                (
+                    BuilderDataType::String(Cow::from(k)),
+                    BuilderDataType::Integer(0),
+                )
+            })
+            .collect(),
+    );
+
+    let test: Entries = from_data(data.clone()).unwrap();
+    assert_eq!(vec!["c", "a", "b"], test.0);
+
+    let test: Entries = from_data_sorted_maps(data.clone()).unwrap();
+    assert_eq!(vec!["a", "b", "c"], test.0);
+
+    // `Repeat` walks its body by reference, covering the borrowed map access.
+    let data = BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(1), data]);
+    let test: Vec<Entries> = from_data_sorted_maps(data).unwrap();
+    assert_eq!(vec!["a", "b", "c"], test[0].0);
+}
+
+#[test]
+fn test_deep_reference_chain() {
+    fn chain(depth: usize) -> BuilderDataType<'static> {
+        (0..depth).fold(BuilderDataType::Integer(7), |data, _| {
+            BuilderDataType::Reference(Rc::new(data))
+        })
+    }
+
+    fn drop_iteratively(mut data: BuilderDataType) {
+        while let BuilderDataType::Reference(r) = data {
+            match Rc::try_unwrap(r) {
+                Ok(inner) => data = inner,
+                Err(_) => break,
+            }
+        }
+    }
+
+    // Run on a small stack so one frame per layer would overflow.
+    std::thread::Builder::new()
+        .stack_size(256 * 1024)
+        .spawn(|| {
+            let data = chain(10_000);
+            let test: i64 = from_ref(&data).unwrap();
+            assert_eq!(7, test);
+            drop_iteratively(data);
+
+            let test: i64 = from_data(chain(10_000)).unwrap();
+            assert_eq!(7, test);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_apply() {
+    let data = BuilderDataType::Apply(
+        Box::new(BuilderDataType::List(vec![
+            BuilderDataType::Argument(0),
+            BuilderDataType::Argument(1),
+            BuilderDataType::ArgumentOr(2, Box::new(BuilderDataType::Integer(3))),
+        ])),
+        vec![BuilderDataType::Integer(1), BuilderDataType::Integer(2)],
+    );
+
+    let test: (i64, i64, i64) = from_ref(&data).unwrap();
+    assert_eq!((1, 2, 3), test);
+
+    let test: (i64, i64, i64) = from_data(data).unwrap();
+    assert_eq!((1, 2, 3), test);
+}
+
+#[test]
+fn test_borrowed_bytes() {
+    #[derive(Debug, Deserialize)]
+    struct Record<'a> {
+        data: &'a [u8],
+    }
+
+    let source = vec![1u8, 2, 3];
+    let data = BuilderDataType::Map(vec![(
+        BuilderDataType::String(Cow::from("data")),
+        BuilderDataType::Bytes(Cow::Borrowed(source.as_slice())),
+    )]);
+
+    let test: Record = from_ref(&data).unwrap();
+    assert_eq!(source.as_ptr(), test.data.as_ptr());
+
+    let test: Record = from_data(data).unwrap();
+    assert_eq!(source.as_ptr(), test.data.as_ptr());
+}
+
+#[test]
+fn test_push_and_insert() {
+    let mut list = BuilderDataType::Empty;
+    list.push(BuilderDataType::Integer(123));
+    list.push(BuilderDataType::Boolean(true));
+    list.extend([BuilderDataType::String(Cow::from("test"))]);
+
+    let test: TestSimple = from_ref(&list).unwrap();
+    assert_eq!(
+        TestSimple {
+            a: 123,
+            b: true,
+            c: "test".to_owned(),
+        },
+        test
+    );
+
+    let mut map = BuilderDataType::Empty;
+    map.insert(
+        BuilderDataType::String(Cow::from("a")),
+        BuilderDataType::Integer(1),
+    )
+    .unwrap();
+    map.insert(
+        BuilderDataType::String(Cow::from("b")),
+        BuilderDataType::Integer(2),
+    )
+    .unwrap();
+
+    let test: BTreeMap<String, i64> = from_data(map).unwrap();
+    assert_eq!(
+        BTreeMap::from([("a".to_owned(), 1), ("b".to_owned(), 2)]),
+        test
+    );
+
+    let mut scalar = BuilderDataType::Integer(1);
+    assert_eq!(
+        Err(BuilderError::TypeMismatch {
+            expected: "Map",
+            found: "Integer",
+        }),
+        scalar.insert(BuilderDataType::Empty, BuilderDataType::Empty)
+    );
+    scalar.push(BuilderDataType::Integer(2));
+    let test: Vec<i64> = from_data(scalar).unwrap();
+    assert_eq!(vec![1, 2], test);
+}
+
+#[test]
+fn test_integral_number_into_integer() {
+    let data = BuilderDataType::Number(3.0);
+
+    assert_eq!(Ok(3), from_ref::<i32>(&data));
+    assert_eq!(Ok(3.0), from_ref::<f64>(&data));
+    assert_eq!(Ok(3), from_data::<i32>(data.clone()));
+    assert_eq!(Ok(3.0), from_data::<f64>(data));
+
+    assert!(from_data::<i32>(BuilderDataType::Number(3.5)).is_err());
+    assert_eq!(Ok(3.5), from_data::<f64>(BuilderDataType::Number(3.5)));
+}
+
+#[test]
+fn test_tap() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Tap(vec![
+            BuilderDataType::String(Cow::from("total")),
+            BuilderDataType::Sum(vec![
+                BuilderDataType::Integer(40),
+                BuilderDataType::Integer(2),
+            ]),
+        ]),
+        BuilderDataType::Boolean(true),
+    ]);
+
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorded = calls.clone();
+    let test: (i64, bool) = from_data_with_tap(data.clone(), move |label, value| {
+        recorded
+            .borrow_mut()
+            .push((label.to_owned(), value.to_owned()))
+    })
+    .unwrap();
+
+    assert_eq!((42, true), test);
+    assert_eq!(vec![("total".to_owned(), "42".to_owned())], *calls.borrow());
+
+    let test: (i64, bool) = from_data(data).unwrap();
+    assert_eq!((42, true), test);
+}
+
+#[test]
+fn test_internally_tagged_enum() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(tag = "type")]
+    enum Shape {
+        Circle { radius: f64 },
+        Rect { w: i32, h: i32 },
+    }
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Map(vec![
+            (
+                BuilderDataType::String(Cow::from("radius")),
+                BuilderDataType::Number(1.5),
+            ),
+            (
+                BuilderDataType::String(Cow::from("type")),
+                BuilderDataType::String(Cow::from("Circle")),
+            ),
+        ]),
+        BuilderDataType::Map(vec![
+            (
+                BuilderDataType::String(Cow::from("type")),
+                BuilderDataType::String(Cow::from("Rect")),
+            ),
+            (
+                BuilderDataType::String(Cow::from("w")),
+                BuilderDataType::Integer(2),
+            ),
+            (
+                BuilderDataType::String(Cow::from("h")),
+                BuilderDataType::Sum(vec![
+                    BuilderDataType::Integer(1),
+                    BuilderDataType::Integer(2),
+                ]),
+            ),
+        ]),
+    ]);
+    let expected = vec![Shape::Circle { radius: 1.5 }, Shape::Rect { w: 2, h: 3 }];
+
+    let test: Vec<Shape> = from_ref(&data).unwrap();
+    assert_eq!(expected, test);
+
+    let test: Vec<Shape> = from_data(data).unwrap();
+    assert_eq!(expected, test);
+}
+
+#[test]
+fn test_array_length() {
+    let list = |n| BuilderDataType::List((1..=n).map(BuilderDataType::Integer).collect());
+
+    assert_eq!(Ok([1, 2, 3]), from_ref::<[i32; 3]>(&list(3)));
+    assert_eq!(Ok([1, 2, 3]), from_data::<[i32; 3]>(list(3)));
+
+    let mismatch = Err(BuilderError::ArrayLengthMismatch {
+        expected: 3,
+        found: 2,
+    });
+    assert_eq!(mismatch, from_ref::<[i32; 3]>(&list(2)));
+    assert_eq!(mismatch, from_data::<[i32; 3]>(list(2)));
+}
+
+#[test]
+fn test_path() {
+    let key = |k: &'static str| BuilderDataType::String(Cow::from(k));
+    let root = BuilderDataType::Map(vec![(
+        key("a"),
+        BuilderDataType::List(vec![
+            BuilderDataType::Empty,
+            BuilderDataType::Map(vec![
+                (key("b"), BuilderDataType::Integer(1)),
+                (key("c"), BuilderDataType::Integer(42)),
+            ]),
+        ]),
+    )]);
+    let path = |segments: Vec<BuilderDataType<'static>>| {
+        BuilderDataType::Path([root.clone()].into_iter().chain(segments).collect())
+    };
+
+    let data = path(vec![key("a"), BuilderDataType::Unsigned(1), key("c")]);
+    assert_eq!(Ok(42), from_ref::<i64>(&data));
+    assert_eq!(Ok(42), from_data::<i64>(data));
+
+    let data = path(vec![key("a"), BuilderDataType::Integer(5), key("c")]);
+    assert_eq!(Ok(None), from_ref::<Option<i64>>(&data));
+    assert_eq!(Ok(None), from_data::<Option<i64>>(data));
+}
+
+#[test]
+fn test_stats() {
+    let data = BuilderDataType::Closure(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::Argument(1),
+            BuilderDataType::Argument(1),
+        ]),
+        BuilderDataType::Integer(5),
+    ]);
+
+    let (test, stats): ((i64, i64), Stats) = from_data_with_stats(data).unwrap();
+
+    assert_eq!((5, 5), test);
+    // Integer fields resolve `Argument` in place, so only the resolved values are visited.
+    assert_eq!(
+        BTreeMap::from([("Closure", 1), ("Integer", 2), ("List", 1)]),
+        stats.visits
+    );
+    assert_eq!(3, stats.max_depth);
+    // Arguments are shared with the closure body rather than cloned into it.
+    assert_eq!(0, stats.clones);
+}
+
+#[test]
+fn test_recursion_limit() {
+    let mut data = BuilderDataType::Integer(1);
+    for _ in 0..1_000 {
+        data = BuilderDataType::List(vec![data]);
+    }
+    assert_eq!(
+        Err(BuilderError::RecursionLimit(64)),
+        from_ref::<serde::de::IgnoredAny>(&data)
+    );
+    assert_eq!(
+        Err(BuilderError::RecursionLimit(64)),
+        from_data::<serde::de::IgnoredAny>(data)
+    );
+
+    let data = BuilderDataType::List(vec![BuilderDataType::List(vec![BuilderDataType::Integer(
+        1,
+    )])]);
+    let config = DeserializerConfig::new().recursion_limit(2);
+    assert_eq!(
+        Err(BuilderError::RecursionLimit(2)),
+        config.deserialize_ref::<serde::de::IgnoredAny>(&data)
+    );
+    let test: Vec<Vec<i64>> = config.recursion_limit(3).deserialize(data).unwrap();
+    assert_eq!(vec![vec![1]], test);
+}
+
+#[test]
+fn test_config_tap_and_stats() {
+    let data = BuilderDataType::Tap(vec![
+        BuilderDataType::String(Cow::from("n")),
+        BuilderDataType::Integer(7),
+    ]);
+
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorded = calls.clone();
+    let stats = Rc::new(RefCell::new(Stats::default()));
+    let config = DeserializerConfig::new()
+        .tap(move |label, value| {
+            recorded
+                .borrow_mut()
+                .push((label.to_owned(), value.to_owned()))
+        })
+        .stats(stats.clone());
+
+    let test: i64 = config.deserialize_ref(&data).unwrap();
+    assert_eq!(7, test);
+    assert_eq!(vec![("n".to_owned(), "7".to_owned())], *calls.borrow());
+    assert_eq!(1, stats.borrow().max_depth);
+}
+
+#[test]
+fn test_condition_evaluated_once() {
+    let store = |values: Vec<i64>| {
+        Rc::new(RefCell::new(BuilderDataType::List(
+            values.into_iter().map(BuilderDataType::Integer).collect(),
+        )))
+    };
+    // The condition picks a `Sum` that drains the store, nested so that it is only reached
+    // through another `IfThenElse`.
+    let data = |r: &Rc<RefCell<BuilderDataType<'static>>>| {
+        BuilderDataType::IfThenElse(vec![
+            BuilderDataType::IfThenElse(vec![
+                BuilderDataType::Boolean(true),
+                BuilderDataType::Sum(vec![BuilderDataType::Take(r.clone())]),
+                BuilderDataType::Empty,
+            ]),
+            BuilderDataType::String(Cow::from("yes")),
+            BuilderDataType::String(Cow::from("no")),
+        ])
+    };
+    let remaining = |r: &Rc<RefCell<BuilderDataType>>| match &*r.borrow() {
+        BuilderDataType::List(v) => v.len(),
+        _ => unreachable!(),
+    };
+
+    let r = store(vec![0, 5]);
+    assert_eq!(Ok("yes".to_owned()), from_ref::<String>(&data(&r)));
+    assert_eq!(1, remaining(&r));
+    assert_eq!(Ok("no".to_owned()), from_ref::<String>(&data(&r)));
+    assert_eq!(0, remaining(&r));
+
+    let r = store(vec![0, 5]);
+    assert_eq!(Ok("yes".to_owned()), from_data::<String>(data(&r)));
+    assert_eq!(1, remaining(&r));
+    assert_eq!(Ok("no".to_owned()), from_data::<String>(data(&r)));
+    assert_eq!(0, remaining(&r));
+}
+
+#[test]
+fn test_null_and_empty() {
+    // Reads `Empty` as absent and `Null` as present but null.
+    fn double_option<'de, D>(deserializer: D) -> Result<Option<Option<i32>>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct DoubleOption;
+
+        impl<'de> serde::de::Visitor<'de> for DoubleOption {
+            type Value = Option<Option<i32>>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an optional nullable integer")
+            }
+
+            fn visit_none<E>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_unit<E>(self) -> Result<Self::Value, E> {
+                Ok(Some(None))
+            }
+
+            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                i32::deserialize(deserializer).map(|v| Some(Some(v)))
+            }
+        }
+
+        deserializer.deserialize_option(DoubleOption)
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Record {
+        #[serde(deserialize_with = "double_option")]
+        value: Option<Option<i32>>,
+        plain: Option<i32>,
+    }
+
+    let record = |value, plain| {
+        BuilderDataType::Map(vec![
+            (BuilderDataType::String(Cow::from("value")), value),
+            (BuilderDataType::String(Cow::from("plain")), plain),
+        ])
+    };
+
+    for (data, expected) in [
+        (
+            record(BuilderDataType::Empty, BuilderDataType::Null),
+            Record {
+                value: None,
+                plain: None,
+            },
+        ),
+        (
+            record(BuilderDataType::Null, BuilderDataType::Empty),
+            Record {
+                value: Some(None),
+                plain: None,
+            },
+        ),
+        (
+            record(BuilderDataType::Integer(1), BuilderDataType::Integer(2)),
+            Record {
+                value: Some(Some(1)),
+                plain: Some(2),
+            },
+        ),
+    ] {
+        assert_eq!(Ok(&expected), from_ref::<Record>(&data).as_ref());
+        assert_eq!(Ok(expected), from_data::<Record>(data));
+    }
+}
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|c| c.get())
+}
+
+#[test]
+fn test_shared_store_is_not_cloned() {
+    let store = Rc::new(RefCell::new(BuilderDataType::List(
+        (0..1000).map(BuilderDataType::Integer).collect(),
+    )));
+    let data = BuilderDataType::List(
+        (0..100)
+            .map(|_| BuilderDataType::Store(store.clone()))
+            .collect(),
+    );
+
+    // The scope allocates its shared state up front, so only the deserialization is counted.
+    let mut closure = Closure::new();
+    let before = allocations();
+    let _ = serde::de::IgnoredAny::deserialize(BuilderDeserializerRef {
+        closure: &mut closure,
+        data: &data,
+    })
+    .unwrap();
+    assert_eq!(before, allocations());
+}
+
+#[test]
+fn test_owned_strings_are_moved() {
+    let data = BuilderDataType::List(
+        (0..5000)
+            .map(|i| BuilderDataType::String(Cow::Owned(format!("key{}", i % 3))))
+            .collect(),
+    );
+
+    // Owned strings are handed to the visitor as they are, so only the outer `Vec` is allocated
+    // however many strings repeat.
+    let before = allocations();
+    let test: Vec<String> = from_data(data).unwrap();
+    assert!(allocations() - before < 10);
+    assert_eq!("key1", test[4999]);
+}
+
+#[test]
+fn test_wrong_arity() {
+    let wrong_arity = |op, expected, found| BuilderError::WrongArity {
+        op,
+        expected,
+        found,
+    };
+
+    let data = BuilderDataType::IfThenElse(vec![
+        BuilderDataType::Boolean(true),
+        BuilderDataType::Integer(1),
+    ]);
+    assert_eq!(Err(wrong_arity("IfThenElse", 3, 2)), from_ref::<i32>(&data));
+    assert_eq!(Err(wrong_arity("IfThenElse", 3, 2)), from_data::<i32>(data));
+
+    let data = BuilderDataType::List(vec![BuilderDataType::IfThenElse(vec![
+        BuilderDataType::Boolean(true),
+    ])]);
+    assert_eq!(
+        Err(wrong_arity("IfThenElse", 3, 1)),
+        from_data::<Vec<i32>>(data)
+    );
+
+    let data = BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(3)]);
+    assert_eq!(
+        Err(wrong_arity("Repeat", 2, 1)),
+        from_ref::<Vec<i32>>(&data)
+    );
+    assert_eq!(
+        Err(wrong_arity("Repeat", 2, 1)),
+        from_data::<Vec<i32>>(data)
+    );
+
+    let data = BuilderDataType::Repeat(vec![]);
+    assert_eq!(
+        Err(wrong_arity("Repeat", 2, 0)),
+        from_data::<Vec<i32>>(data)
+    );
+
+    // Too few operands for every multi-operand operation.
+    let one = || vec![BuilderDataType::Integer(1)];
+    let two = || vec![BuilderDataType::Integer(1), BuilderDataType::Integer(2)];
+    let cases = [
+        (BuilderDataType::While(one()), "While", 2, 1),
+        (BuilderDataType::Try(one()), "Try", 2, 1),
+        (BuilderDataType::Tap(one()), "Tap", 2, 1),
+        (BuilderDataType::Range(one()), "Range", 2, 1),
+        (BuilderDataType::Scan(two()), "Scan", 3, 2),
+        (BuilderDataType::Pad(two()), "Pad", 3, 2),
+        (BuilderDataType::Let(two()), "Let", 3, 2),
+        (BuilderDataType::Assert(one()), "Assert", 2, 1),
+        (BuilderDataType::ParseInt(vec![]), "ParseInt", 1, 0),
+        (BuilderDataType::ParseFloat(vec![]), "ParseFloat", 1, 0),
+        (BuilderDataType::Duration(vec![]), "Duration", 1, 0),
+        (BuilderDataType::Path(vec![]), "Path", 1, 0),
+        (BuilderDataType::Sum(vec![]), "Sum", 1, 0),
+        (BuilderDataType::Multiply(vec![]), "Multiply", 1, 0),
+        (BuilderDataType::Divide(vec![]), "Divide", 1, 0),
+        (BuilderDataType::Switch(one()), "Switch", 2, 1),
+        (BuilderDataType::Weighted(vec![]), "Weighted", 2, 0),
+    ];
+    for (data, op, expected, found) in cases {
+        let error = wrong_arity(op, expected, found);
+        assert_eq!(Err(vec![error.clone()]), data.validate());
+        assert_eq!(
+            Err(error.clone()),
+            from_ref::<serde::de::IgnoredAny>(&data).map(|_| ())
+        );
+        assert_eq!(
+            Err(error),
+            from_data::<serde::de::IgnoredAny>(data).map(|_| ())
+        );
+    }
+
+    // Optional operands have an upper bound too.
+    let data = BuilderDataType::ParseInt(vec![BuilderDataType::Integer(1); 3]);
+    assert_eq!(Err(wrong_arity("ParseInt", 2, 3)), from_ref::<i64>(&data));
+}
+
+#[test]
+fn test_range() {
+    let data = BuilderDataType::Range(vec![
+        BuilderDataType::Integer(0),
+        BuilderDataType::Integer(3),
+    ]);
+    assert_eq!(Ok(vec![0, 1, 2]), from_ref::<Vec<i64>>(&data));
+    assert_eq!(Ok(vec![0, 1, 2]), from_data::<Vec<i64>>(data));
+
+    let data = BuilderDataType::Range(vec![
+        BuilderDataType::Integer(3),
+        BuilderDataType::Integer(-3),
+    ]);
+    assert_eq!(Ok(vec![]), from_ref::<Vec<i64>>(&data));
+
+    let data = BuilderDataType::Range(vec![
+        BuilderDataType::Integer(0),
+        BuilderDataType::Unsigned(u64::MAX),
+    ]);
+    assert!(matches!(
+        from_ref::<Vec<i64>>(&data),
+        Err(BuilderError::IterationLimit(_))
+    ));
+}
+
+#[test]
+fn test_unsigned_into_i128() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Wide {
+        signed: i128,
+        unsigned: u128,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("signed")),
+            BuilderDataType::Unsigned(u64::MAX),
+        ),
+        (
+            BuilderDataType::String(Cow::from("unsigned")),
+            BuilderDataType::Unsigned(u64::MAX),
+        ),
+    ]);
+    let expected = Wide {
+        signed: i128::from(u64::MAX),
+        unsigned: u128::from(u64::MAX),
+    };
+
+    assert_eq!(Ok(&expected), from_ref::<Wide>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Wide>(data));
+}
+
+#[test]
+fn test_validate() {
+    let count = Rc::new(RefCell::new(BuilderDataType::Integer(3)));
+    let guarded = Rc::new_cyclic(|self_reference| {
+        BuilderDataType::IfThenElse(vec![
+            BuilderDataType::Take(count.clone()),
+            BuilderDataType::List(vec![BuilderDataType::SelfReference(self_reference.clone())]),
+            BuilderDataType::List(vec![]),
+        ])
+    });
+    assert_eq!(Ok(()), guarded.validate());
+
+    let closure = BuilderDataType::Closure(vec![
+        BuilderDataType::Apply(
+            Box::new(BuilderDataType::Argument(1)),
+            vec![BuilderDataType::Argument(1), BuilderDataType::Integer(2)],
+        ),
+        BuilderDataType::Integer(1),
+    ]);
+    assert_eq!(Ok(()), closure.validate());
+
+    let unguarded = Rc::new_cyclic(|self_reference| {
+        BuilderDataType::List(vec![BuilderDataType::SelfReference(self_reference.clone())])
+    });
+    let broken = BuilderDataType::List(vec![
+        BuilderDataType::IfThenElse(vec![BuilderDataType::Boolean(true)]),
+        BuilderDataType::Argument(0),
+        BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(2)]),
+        BuilderDataType::SelfReference(Weak::new()),
+        BuilderDataType::Reference(unguarded),
+        BuilderDataType::Apply(
+            Box::new(BuilderDataType::Argument(1)),
+            vec![BuilderDataType::Integer(2)],
+        ),
+    ]);
+    assert_eq!(
+        Err(vec![
+            BuilderError::WrongArity {
+                op: "IfThenElse",
+                expected: 3,
+                found: 1,
+            },
+            BuilderError::InvalidFunctionArgument,
+            BuilderError::WrongArity {
+                op: "Repeat",
+                expected: 2,
+                found: 1,
+            },
+            BuilderError::InvalidSelfRefrence,
+            BuilderError::UnguardedCycle,
+            BuilderError::InvalidFunctionArgument,
+        ]),
+        broken.validate()
+    );
+}
+
+#[test]
+fn test_validate_scopes() {
+    let s = |s: &'static str| BuilderDataType::String(Cow::from(s));
+    let valid = [
+        BuilderDataType::Scan(vec![
+            BuilderDataType::List(vec![BuilderDataType::Integer(1)]),
+            BuilderDataType::Integer(0),
+            BuilderDataType::Sum(vec![
+                BuilderDataType::Argument(0),
+                BuilderDataType::Argument(1),
+            ]),
+        ]),
+        BuilderDataType::Partition(vec![
+            BuilderDataType::List(vec![BuilderDataType::Integer(1)]),
+            BuilderDataType::Argument(0),
+        ]),
+        BuilderDataType::Apply(
+            Box::new(BuilderDataType::Argument(1)),
+            vec![BuilderDataType::Integer(1), BuilderDataType::Integer(2)],
+        ),
+        BuilderDataType::DefineFn(vec![
+            s("double"),
+            BuilderDataType::Multiply(vec![
+                BuilderDataType::Argument(0),
+                BuilderDataType::Integer(2),
+            ]),
+            BuilderDataType::CallFn(vec![s("double"), BuilderDataType::Integer(3)]),
+        ]),
+        BuilderDataType::Let(vec![
+            s("x"),
+            BuilderDataType::Integer(3),
+            BuilderDataType::Var(Box::new(s("x"))),
+        ]),
+        BuilderDataType::Unfold(vec![
+            BuilderDataType::Integer(1),
+            BuilderDataType::Integer(3),
+            BuilderDataType::Multiply(vec![
+                BuilderDataType::Argument(0),
+                BuilderDataType::Integer(2),
+            ]),
+        ]),
+    ];
+    for data in valid {
+        assert_eq!(Ok(()), data.validate(), "{}", data.pretty());
+        assert!(from_ref::<serde_json::Value>(&data).is_ok());
+    }
+
+    // Arguments of the enclosing scope are still checked against it.
+    let data = BuilderDataType::Scan(vec![
+        BuilderDataType::Argument(0),
+        BuilderDataType::Integer(0),
+        BuilderDataType::Argument(1),
+    ]);
+    assert_eq!(
+        Err(vec![BuilderError::InvalidFunctionArgument]),
+        data.validate()
+    );
+}
+
+#[test]
+fn test_scalar_into_string() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Labels {
+        id: String,
+        flag: String,
+        ratio: String,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("id")),
+            BuilderDataType::Integer(42),
+        ),
+        (
+            BuilderDataType::String(Cow::from("flag")),
+            BuilderDataType::Boolean(true),
+        ),
+        (
+            BuilderDataType::String(Cow::from("ratio")),
+            BuilderDataType::Number(0.5),
+        ),
+    ]);
+    let expected = Labels {
+        id: "42".to_owned(),
+        flag: "true".to_owned(),
+        ratio: "0.5".to_owned(),
+    };
+
+    assert_eq!(Ok(&expected), from_ref::<Labels>(&data).as_ref());
+    assert!(from_data_strict::<Labels>(data.clone()).is_err());
+    assert_eq!(Ok(expected), from_data::<Labels>(data));
+}
+
+#[test]
+fn test_repeat_map() {
+    let data = BuilderDataType::RepeatMap(vec![
+        BuilderDataType::Unsigned(3),
+        BuilderDataType::Index,
+        BuilderDataType::Multiply(vec![BuilderDataType::Index, BuilderDataType::Integer(2)]),
+    ]);
+    let expected = BTreeMap::from([(0, 0), (1, 2), (2, 4)]);
+
+    assert_eq!(
+        Ok(&expected),
+        from_ref::<BTreeMap<u32, u32>>(&data).as_ref()
+    );
+    assert_eq!(Ok(expected), from_data::<BTreeMap<u32, u32>>(data));
+}
+
+#[test]
+fn test_large_argument_is_shared() {
+    let large = || BuilderDataType::List((0..1000).map(BuilderDataType::Integer).collect());
+    let data = BuilderDataType::Closure(vec![
+        BuilderDataType::Repeat(vec![
+            BuilderDataType::Unsigned(50),
+            BuilderDataType::Argument(1),
+        ]),
+        large(),
+    ]);
+    let expected = vec![(0..1000).collect::<Vec<i64>>(); 50];
+
+    assert_eq!(Ok(&expected), from_ref::<Vec<Vec<i64>>>(&data).as_ref());
+    let (test, stats) = from_data_with_stats::<Vec<Vec<i64>>>(data).unwrap();
+    assert_eq!(expected, test);
+    assert_eq!(0, stats.clones);
+}
+
+#[test]
+fn test_struct_from_numeric_keys() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Sparse {
+        first: i32,
+        #[serde(default)]
+        second: i32,
+        third: String,
+    }
+
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::Unsigned(2),
+            BuilderDataType::String(Cow::from("c")),
+        ),
+        (BuilderDataType::Integer(0), BuilderDataType::Integer(1)),
+    ]);
+    let expected = Sparse {
+        first: 1,
+        second: 0,
+        third: "c".to_owned(),
+    };
+
+    assert_eq!(Ok(&expected), from_ref::<Sparse>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Sparse>(data.clone()));
+
+    // A numeric key and its field name are the same key.
+    let mut duplicated = data;
+    duplicated
+        .insert(
+            BuilderDataType::String(Cow::from("first")),
+            BuilderDataType::Integer(2),
+        )
+        .unwrap();
+    assert_eq!(
+        Err(BuilderError::DuplicateKey("first".to_owned())),
+        from_data_with_duplicate_keys::<Sparse>(duplicated, DuplicateKeyPolicy::Error)
+    );
+}
+
+#[test]
+fn test_lazy_captures_arguments() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Captured {
+        value: i32,
+    }
+
+    let call = |argument: BuilderDataType<'static>| {
+        BuilderDataType::Closure(vec![
+            BuilderDataType::Apply(
+                Box::new(BuilderDataType::Argument(0)),
+                vec![argument, BuilderDataType::Integer(100)],
+            ),
+            BuilderDataType::Integer(7),
+        ])
+    };
+    let map = || {
+        BuilderDataType::Map(vec![(
+            BuilderDataType::String(Cow::from("value")),
+            BuilderDataType::Argument(1),
+        )])
+    };
+
+    // Without `Lazy` the map is only evaluated in the `Apply` body, where `Argument(1)` is 100.
+    let data = call(map());
+    assert_eq!(Ok(Captured { value: 100 }), from_ref::<Captured>(&data));
+    assert_eq!(Ok(Captured { value: 100 }), from_data::<Captured>(data));
+
+    let data = call(BuilderDataType::Lazy(Box::new(map())));
+    assert_eq!(Ok(Captured { value: 7 }), from_ref::<Captured>(&data));
+    assert_eq!(Ok(Captured { value: 7 }), from_data::<Captured>(data));
+}
+
+#[test]
+fn test_empty_closure() {
+    let data = BuilderDataType::Closure(vec![]);
+
+    assert_eq!(Err(BuilderError::EmptyClosure), from_ref::<i32>(&data));
+    assert_eq!(
+        Err(BuilderError::EmptyClosure),
+        from_data::<i32>(data.clone())
+    );
+    assert_eq!(Err(vec![BuilderError::EmptyClosure]), data.validate());
+}
+
+#[test]
+fn test_deserializer_config() {
+    let config = DeserializerConfig::new()
+        .strict(true)
+        .args(vec![BuilderDataType::Integer(5)]);
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Argument(0),
+        BuilderDataType::Boolean(true),
+    ]);
+    assert_eq!(Ok((5, true)), config.deserialize_ref::<(i32, bool)>(&data));
+    assert_eq!(Ok((5, true)), config.deserialize::<(i32, bool)>(data));
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Argument(0),
+        BuilderDataType::Integer(1),
+    ]);
+    assert!(config.deserialize::<(i32, bool)>(data).is_err());
+
+    let config = DeserializerConfig::new()
+        .sort_maps(true)
+        .duplicate_keys(DuplicateKeyPolicy::First);
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("b")),
+            BuilderDataType::Integer(1),
+        ),
+        (
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Integer(2),
+        ),
+        (
+            BuilderDataType::String(Cow::from("b")),
+            BuilderDataType::Integer(3),
+        ),
+    ]);
+    assert_eq!(
+        Ok(BTreeMap::from([("a".to_owned(), 2), ("b".to_owned(), 1)])),
+        config.deserialize::<BTreeMap<String, i32>>(data)
+    );
+}
+
+#[test]
+fn test_quote() {
+    let quote = |style: &'static str| {
+        BuilderDataType::Quote(vec![
+            BuilderDataType::String(Cow::from("say \"hi\", it's\n")),
+            BuilderDataType::String(Cow::from(style)),
+        ])
+    };
+
+    for (style, expected) in [
+        ("json", r#""say \"hi\", it's\n""#),
+        ("csv", "\"say \"\"hi\"\", it's\n\""),
+        ("shell", "'say \"hi\", it'\\''s\n'"),
+    ] {
+        assert_eq!(Ok(expected.to_owned()), from_ref::<String>(&quote(style)));
+        assert_eq!(Ok(expected.to_owned()), from_data::<String>(quote(style)));
+    }
+
+    let plain = BuilderDataType::Quote(vec![
+        BuilderDataType::Integer(42),
+        BuilderDataType::String(Cow::from("csv")),
+    ]);
+    assert_eq!(Ok("42".to_owned()), from_data::<String>(plain));
+    assert_eq!(
+        Err(BuilderError::InvalidFunctionArgument),
+        from_data::<String>(quote("xml"))
+    );
+}
+
+#[test]
+fn test_reference_moves_only_when_unique() {
+    #[derive(Debug, PartialEq)]
+    enum Visited {
+        Str,
+        String,
+    }
+
+    impl<'de> Deserialize<'de> for Visited {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct VisitedVisitor;
+
+            impl<'de> serde::de::Visitor<'de> for VisitedVisitor {
+                type Value = Visited;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a string")
+                }
+
+                fn visit_str<E>(self, _: &str) -> Result<Visited, E> {
+                    Ok(Visited::Str)
+                }
+
+                fn visit_string<E>(self, _: String) -> Result<Visited, E> {
+                    Ok(Visited::String)
+                }
+            }
+
+            deserializer.deserialize_any(VisitedVisitor)
+        }
+    }
+
+    let value = || BuilderDataType::String(Cow::Owned("value".to_owned()));
+
+    let unique = BuilderDataType::Reference(Rc::new(value()));
+    assert_eq!(Ok(Visited::String), from_data::<Visited>(unique));
+
+    let shared = Rc::new(value());
+    let data = BuilderDataType::Reference(shared.clone());
+    assert_eq!(2, Rc::strong_count(&shared));
+    assert_eq!(Ok(Visited::Str), from_data::<Visited>(data));
+    assert_eq!(1, Rc::strong_count(&shared));
+}
+
+#[test]
+fn test_merge_duplicate_maps() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Inner {
+        a: i32,
+        b: i32,
+        deep: BTreeMap<String, i32>,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Outer {
+        inner: Inner,
+    }
+
+    let entry =
+        |k: &'static str, v: BuilderDataType<'static>| (BuilderDataType::String(Cow::from(k)), v);
+    let data = BuilderDataType::Map(vec![
+        entry(
+            "inner",
+            BuilderDataType::Map(vec![
+                entry("a", BuilderDataType::Integer(1)),
+                entry(
+                    "deep",
+                    BuilderDataType::Map(vec![entry("x", BuilderDataType::Integer(1))]),
+                ),
+            ]),
+        ),
+        entry(
+            "inner",
+            BuilderDataType::Map(vec![
+                entry("b", BuilderDataType::Integer(2)),
+                entry(
+                    "deep",
+                    BuilderDataType::Map(vec![entry("y", BuilderDataType::Integer(2))]),
+                ),
+            ]),
+        ),
+    ]);
+    let expected = Outer {
+        inner: Inner {
+            a: 1,
+            b: 2,
+            deep: BTreeMap::from([("x".to_owned(), 1), ("y".to_owned(), 2)]),
+        },
+    };
+
+    assert!(from_ref::<Outer>(&data).is_err());
+    let config = DeserializerConfig::new().duplicate_keys(DuplicateKeyPolicy::Merge);
+    assert_eq!(
+        Ok(&expected),
+        config.deserialize_ref::<Outer>(&data).as_ref()
+    );
+    assert_eq!(Ok(expected), config.deserialize::<Outer>(data));
+}
+
+#[test]
+fn test_try_from_scalars() {
+    assert_eq!(Ok(-3), i64::try_from(BuilderDataType::Integer(-3)));
+    assert_eq!(
+        Ok(7),
+        i64::try_from(BuilderDataType::String(Cow::from(" 7 ")))
+    );
+    assert_eq!(Ok(4), u64::try_from(BuilderDataType::Unsigned(4)));
+    assert_eq!(Ok(1), u64::try_from(BuilderDataType::Boolean(true)));
+    assert_eq!(Ok(2.5), f64::try_from(BuilderDataType::Number(2.5)));
+    assert_eq!(Ok(true), bool::try_from(BuilderDataType::Integer(1)));
+    assert_eq!(
+        Ok("12".to_owned()),
+        String::try_from(BuilderDataType::Reference(Rc::new(
+            BuilderDataType::Unsigned(12)
+        )))
+    );
+
+    assert_eq!(
+        Err(BuilderError::ParseFailed("x".to_owned())),
+        i64::try_from(BuilderDataType::String(Cow::from("x")))
+    );
+    assert_eq!(
+        Err(BuilderError::TypeMismatch {
+            expected: "i64",
+            found: "Closure",
+        }),
+        i64::try_from(BuilderDataType::Closure(vec![BuilderDataType::Integer(1)]))
+    );
+    assert_eq!(
+        Err(BuilderError::TypeMismatch {
+            expected: "String",
+            found: "Sum",
+        }),
+        String::try_from(BuilderDataType::Sum(vec![]))
+    );
+    assert!(bool::try_from(BuilderDataType::List(vec![])).is_err());
+}
+
+#[test]
+fn test_numeric_string_in_tuple() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::String(Cow::from("1")),
+        BuilderDataType::Integer(2),
+    ]);
+
+    assert_eq!(Ok((1, 2)), from_ref::<(u32, u32)>(&data));
+    assert_eq!(Ok((1, 2)), from_data::<(u32, u32)>(data.clone()));
+    assert!(from_data_strict::<(u32, u32)>(data).is_err());
+
+    let data = BuilderDataType::List(vec![BuilderDataType::String(Cow::from("one"))]);
+    assert_eq!(
+        Err(BuilderError::ParseFailed("one".to_owned())),
+        from_data::<(u32,)>(data)
+    );
+}
+
+#[test]
+fn test_comment_is_transparent() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Comment(Box::new(BuilderDataType::Integer(1)), Rc::from("first id")),
+        BuilderDataType::Comment(
+            Box::new(BuilderDataType::String(Cow::from("x"))),
+            Rc::from("label"),
+        ),
+    ]);
+
+    assert_eq!(Ok((1, "x".to_owned())), from_ref::<(u8, String)>(&data));
+    assert_eq!(
+        Ok((1, "x".to_owned())),
+        from_data::<(u8, String)>(data.clone())
+    );
+    assert_eq!(
+        r#"(list (comment "first id" 1) (comment "label" "x"))"#,
+        data.pretty()
+    );
+}
+
+#[test]
+fn test_borrow_conflict() {
+    let cell = Rc::new(RefCell::new(BuilderDataType::List(vec![
+        BuilderDataType::Integer(1),
+    ])));
+    let data = BuilderDataType::List(vec![BuilderDataType::Take(cell.clone())]);
+    let guard = cell.borrow();
+    assert_eq!(
+        Err(BuilderError::BorrowConflict),
+        from_ref::<Vec<i32>>(&data)
+    );
+    drop(guard);
+
+    // A `Take` in condition position reports the conflict instead of panicking.
+    let counter = Rc::new(RefCell::new(BuilderDataType::Integer(1)));
+    let data = BuilderDataType::IfThenElse(vec![
+        BuilderDataType::Take(counter.clone()),
+        BuilderDataType::Integer(1),
+        BuilderDataType::Integer(0),
+    ]);
+    let guard = counter.borrow();
+    assert_eq!(Err(BuilderError::BorrowConflict), from_ref::<i32>(&data));
+    assert!(!data.check_true());
+    drop(guard);
+}
+
+#[test]
+fn test_store_with_nested_take() {
+    let cell = Rc::new(RefCell::new(BuilderDataType::Empty));
+    *cell.borrow_mut() = BuilderDataType::List(vec![
+        BuilderDataType::Integer(5),
+        BuilderDataType::Take(cell.clone()),
+    ]);
+    let data = BuilderDataType::Store(cell.clone());
+
+    // The `Take` pops itself, then the 5 when it is read in turn.
+    assert_eq!(Ok(vec![5, 5]), from_ref::<Vec<i32>>(&data));
+    assert!(matches!(&*cell.borrow(), BuilderDataType::List(v) if v.is_empty()));
+
+    *cell.borrow_mut() = BuilderDataType::IfThenElse(vec![
+        BuilderDataType::Take(cell.clone()),
+        BuilderDataType::Integer(1),
+        BuilderDataType::Integer(0),
+    ]);
+    assert_eq!(Ok(0), from_ref::<i32>(&data));
+
+    // Break the cycle so the cell is freed.
+    *cell.borrow_mut() = BuilderDataType::Empty;
+}
+
+#[test]
+fn test_float32() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Float32(0.1),
+        BuilderDataType::Float32(f32::MAX),
+        BuilderDataType::Number(0.5),
+    ]);
+    let expected = vec![0.1f32, f32::MAX, 0.5];
+
+    assert_eq!(Ok(expected.clone()), from_ref::<Vec<f32>>(&data));
+    assert_eq!(Ok(expected), from_data::<Vec<f32>>(data));
+    assert_eq!(
+        Ok(f64::from(0.1f32)),
+        from_data::<f64>(BuilderDataType::Float32(0.1))
+    );
+
+    let data = BuilderDataType::Number(1e39);
+
+    assert_eq!(
+        Err(BuilderError::FloatOverflow(1e39)),
+        from_ref::<f32>(&data)
+    );
+    assert_eq!(
+        Err(BuilderError::FloatOverflow(1e39)),
+        from_data::<f32>(data)
+    );
+    assert_eq!(Ok(1e39), from_data::<f64>(BuilderDataType::Number(1e39)));
+}
+
+#[test]
+fn test_unfold() {
+    let data = BuilderDataType::Unfold(vec![
+        BuilderDataType::Unsigned(1),
+        BuilderDataType::Unsigned(4),
+        BuilderDataType::Multiply(vec![
+            BuilderDataType::Argument(0),
+            BuilderDataType::Unsigned(2),
+        ]),
+    ]);
+
+    assert_eq!(Ok(vec![1, 2, 4, 8]), from_ref::<Vec<u32>>(&data));
+    assert_eq!(Ok(vec![1, 2, 4, 8]), from_data::<Vec<u32>>(data));
+}
+
+#[test]
+fn test_arg_count() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::ArgCount,
+        BuilderDataType::Apply(
+            Box::new(BuilderDataType::List(vec![
+                BuilderDataType::ArgCount,
+                BuilderDataType::Closure(vec![
+                    BuilderDataType::ArgCount,
+                    BuilderDataType::Argument(0),
+                    BuilderDataType::Argument(1),
+                ]),
+            ])),
+            vec![BuilderDataType::Unsigned(1), BuilderDataType::Unsigned(2)],
+        ),
+    ]);
+
+    type Expected = (u64, (u64, u64));
+    assert_eq!(Ok((0, (2, 3))), from_ref::<Expected>(&data));
+    assert_eq!(Ok((0, (2, 3))), from_data::<Expected>(data));
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn test_regex() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::IfThenElse(vec![
+            BuilderDataType::Matches(vec![
+                BuilderDataType::String("order-42".into()),
+                BuilderDataType::String(r"^order-\d+$".into()),
+            ]),
+            BuilderDataType::String("order".into()),
+            BuilderDataType::String("other".into()),
+        ]),
+        BuilderDataType::Matches(vec![
+            BuilderDataType::String("invoice-7".into()),
+            BuilderDataType::String(r"^order-\d+$".into()),
+        ]),
+        BuilderDataType::Capture(vec![
+            BuilderDataType::String("order-42".into()),
+            BuilderDataType::String(r"-(\d+)".into()),
+        ]),
+        BuilderDataType::Capture(vec![
+            BuilderDataType::String("invoice".into()),
+            BuilderDataType::String(r"-(\d+)".into()),
+        ]),
+    ]);
+
+    type Expected = (String, bool, String, Option<String>);
+    let expected = (String::from("order"), false, String::from("42"), None);
+    assert_eq!(Ok(expected.clone()), from_ref::<Expected>(&data));
+    assert_eq!(Ok(expected), from_data::<Expected>(data));
+
+    let data = BuilderDataType::Matches(vec![
+        BuilderDataType::String("text".into()),
+        BuilderDataType::String("(".into()),
+    ]);
+    assert_eq!(
+        Err(BuilderError::InvalidPattern(String::from("("))),
+        from_ref::<bool>(&data)
+    );
+}
+
+#[test]
+fn test_replace() {
+    let replace = |from: &'static str| {
+        BuilderDataType::Replace(vec![
+            BuilderDataType::String("banana".into()),
+            BuilderDataType::String(from.into()),
+            BuilderDataType::String("X".into()),
+        ])
+    };
+    let data = BuilderDataType::List(vec![replace("a"), replace("")]);
+    let expected = vec![String::from("bXnXnX"), String::from("banana")];
+
+    assert_eq!(Ok(expected.clone()), from_ref::<Vec<String>>(&data));
+    assert_eq!(Ok(expected), from_data::<Vec<String>>(data));
+}
+
+#[test]
+fn test_serde_bytes() {
+    #[derive(Debug, Deserialize)]
+    struct Record<'a> {
+        #[serde(with = "serde_bytes")]
+        borrowed: &'a [u8],
+        #[serde(borrow, with = "serde_bytes")]
+        cow: Cow<'a, [u8]>,
+        owned: serde_bytes::ByteBuf,
+        list: Vec<serde_bytes::ByteBuf>,
+        keys: BTreeMap<serde_bytes::ByteBuf, u32>,
+    }
+
+    let source = vec![1u8, 2, 3];
+    let bytes = || BuilderDataType::Bytes(Cow::Borrowed(source.as_slice()));
+    let data = BuilderDataType::Map(vec![
+        (BuilderDataType::String(Cow::from("borrowed")), bytes()),
+        (BuilderDataType::String(Cow::from("cow")), bytes()),
+        (BuilderDataType::String(Cow::from("owned")), bytes()),
+        (
+            BuilderDataType::String(Cow::from("list")),
+            BuilderDataType::List(vec![bytes(), bytes()]),
+        ),
+        (
+            BuilderDataType::String(Cow::from("keys")),
+            BuilderDataType::Map(vec![(bytes(), BuilderDataType::Unsigned(7))]),
+        ),
+    ]);
+
+    let check = |test: Record| {
+        assert_eq!(source.as_ptr(), test.borrowed.as_ptr());
+        assert!(matches!(test.cow, Cow::Borrowed(v) if v.as_ptr() == source.as_ptr()));
+        assert_eq!(source, test.owned.into_vec());
+        assert_eq!(
+            vec![source.clone(), source.clone()],
+            test.list
+                .into_iter()
+                .map(|b| b.into_vec())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(&7),
+            test.keys.get(&serde_bytes::ByteBuf::from(source.clone()))
+        );
+    };
+    check(from_ref(&data).unwrap());
+    check(from_data(data).unwrap());
+}
+
+#[test]
+fn test_variant() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    enum Shape {
+        Circle(f64),
+        Square { side: u32 },
+    }
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Variant(vec![
+            BuilderDataType::String(Cow::from("Circle")),
+            BuilderDataType::Number(1.5),
+        ]),
+        BuilderDataType::Variant(vec![
+            BuilderDataType::String(Cow::from("Square")),
+            BuilderDataType::Map(vec![(
+                BuilderDataType::String(Cow::from("side")),
+                BuilderDataType::Unsigned(3),
+            )]),
+        ]),
+    ]);
+    let expected = vec![Shape::Circle(1.5), Shape::Square { side: 3 }];
+
+    assert_eq!(Ok(&expected), from_ref::<Vec<Shape>>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<Shape>>(data.clone()));
+
+    let test: Vec<BTreeMap<String, serde_json::Value>> = from_data(data).unwrap();
+    assert_eq!(serde_json::json!(1.5), test[0]["Circle"]);
+}
+
+#[test]
+fn test_self_reference_or() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Node {
+        name: String,
+        parent: Option<Box<Node>>,
+    }
+
+    let parent = Rc::new(BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("name")),
+            BuilderDataType::String(Cow::from("root")),
+        ),
+        (
+            BuilderDataType::String(Cow::from("parent")),
+            BuilderDataType::Empty,
+        ),
+    ]));
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("name")),
+            BuilderDataType::String(Cow::from("leaf")),
+        ),
+        (
+            BuilderDataType::String(Cow::from("parent")),
+            BuilderDataType::SelfReferenceOr(
+                Rc::downgrade(&parent),
+                Box::new(BuilderDataType::Empty),
+            ),
+        ),
+    ]);
+
+    let test: Node = from_ref(&data).unwrap();
+    assert_eq!("root", test.parent.unwrap().name);
+
+    drop(parent);
+    let expected = Node {
+        name: String::from("leaf"),
+        parent: None,
+    };
+    assert_eq!(Ok(&expected), from_ref::<Node>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Node>(data));
+}
+
+#[test]
+fn test_interleave() {
+    let list = |v: &[u64]| {
+        BuilderDataType::List(v.iter().map(|&n| BuilderDataType::Unsigned(n)).collect())
+    };
+    let data = BuilderDataType::Interleave(vec![
+        list(&[1, 3, 5]),
+        BuilderDataType::Reference(Rc::new(list(&[2, 4]))),
+    ]);
+
+    assert_eq!(Ok(vec![1, 2, 3, 4, 5]), from_ref::<Vec<u64>>(&data));
+    assert_eq!(Ok(vec![1, 2, 3, 4, 5]), from_data::<Vec<u64>>(data));
+}
+
+#[test]
+fn test_boolean_into_integer() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Boolean(true),
+        BuilderDataType::Boolean(false),
+    ]);
+
+    assert_eq!(Ok((1, 0)), from_ref::<(u8, i32)>(&data));
+    assert_eq!(Ok((1, 0)), from_data::<(u8, i32)>(data.clone()));
+    assert!(from_data_strict::<(u8, i32)>(data).is_err());
+}
+
+#[test]
+fn test_capped_size_hint() {
+    use serde::de::{MapAccess, SeqAccess, Visitor};
+    use serde::Deserializer;
+
+    /// Reads the size hint and the first element, leaving the rest unvisited.
+    struct FirstWithHint;
+
+    impl<'de> Visitor<'de> for FirstWithHint {
+        type Value = (Option<usize>, Option<u8>);
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a sequence or map")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            Ok((seq.size_hint(), seq.next_element()?))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let hint = map.size_hint();
+            Ok((hint, map.next_entry::<u8, u8>()?.map(|(_, v)| v)))
+        }
+    }
+
+    let huge = BuilderDataType::Unsigned(u64::MAX);
+    let repeat = BuilderDataType::Repeat(vec![huge.clone(), BuilderDataType::Unsigned(7)]);
+    let repeat_map = BuilderDataType::RepeatMap(vec![
+        huge,
+        BuilderDataType::Index,
+        BuilderDataType::Unsigned(7),
+    ]);
+
+    for data in [repeat, repeat_map] {
+        let (hint, first) = BuilderDeserializerRef {
+            closure: &mut Closure::new(),
+            data: &data,
+        }
+        .deserialize_any(FirstWithHint)
+        .unwrap();
+        assert!(hint.unwrap() <= 4096);
+        assert_eq!(Some(7), first);
+
+        let (hint, first) = BuilderDeserializer {
+            closure: &mut Closure::new(),
+            data,
+        }
+        .deserialize_any(FirstWithHint)
+        .unwrap();
+        assert!(hint.unwrap() <= 4096);
+        assert_eq!(Some(7), first);
+    }
+}
+
+#[test]
+fn test_negative_into_unsigned() {
+    let data = BuilderDataType::Integer(-5);
+
+    assert_eq!(
+        Err(BuilderError::NegativeToUnsigned { value: -5 }),
+        from_ref::<u32>(&data)
+    );
+    assert_eq!(
+        Err(BuilderError::NegativeToUnsigned { value: -5 }),
+        from_data::<u32>(data.clone())
+    );
+    assert_eq!(Ok(-5), from_data::<i32>(data));
+
+    let data = BuilderDataType::Sum(vec![
+        BuilderDataType::Integer(-7),
+        BuilderDataType::Integer(2),
+    ]);
+    assert_eq!(
+        Err(BuilderError::NegativeToUnsigned { value: -5 }),
+        from_ref::<u8>(&data)
+    );
+}
+
+#[test]
+fn test_dedup() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Entry {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    let entry = |name: &'static str| {
+        BuilderDataType::Map(vec![
+            (
+                BuilderDataType::String(Cow::from("name")),
+                BuilderDataType::String(Cow::from(name)),
+            ),
+            (
+                BuilderDataType::String(Cow::from("tags")),
+                BuilderDataType::List(vec![
+                    BuilderDataType::String(Cow::from("a")),
+                    BuilderDataType::String(Cow::from("b")),
+                ]),
+            ),
+        ])
+    };
+    let data = BuilderDataType::List(
+        (0..50)
+            .map(|i| entry(if i % 2 == 0 { "even" } else { "odd" }))
+            .collect(),
+    );
+    let deduped = data.clone().dedup();
+
+    let BuilderDataType::List(v) = &deduped else {
+        panic!("expected a list");
+    };
+    let (BuilderDataType::Reference(first), BuilderDataType::Reference(third)) = (&v[0], &v[2])
+    else {
+        panic!("expected shared entries");
+    };
+    assert!(Rc::ptr_eq(first, third));
+
+    let expected = from_ref::<Vec<Entry>>(&data).unwrap();
+    assert_eq!(Ok(&expected), from_ref::<Vec<Entry>>(&deduped).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<Entry>>(deduped.clone()));
+
+    let before = allocations();
+    let copy = data.clone();
+    let full = allocations() - before;
+    drop(copy);
+    let before = allocations();
+    let copy = deduped.clone();
+    let shared = allocations() - before;
+    drop(copy);
+    assert!(shared < full);
+
+    // Equal payloads of different variants, and subtrees holding cells, stay apart.
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::List(vec![BuilderDataType::Integer(1)]),
+        BuilderDataType::List(vec![BuilderDataType::Unsigned(1)]),
+        BuilderDataType::List(vec![BuilderDataType::String(Cow::from("1"))]),
+        BuilderDataType::List(vec![BuilderDataType::Bytes(Cow::from(&b"1"[..]))]),
+        BuilderDataType::List(vec![BuilderDataType::Store(Rc::new(RefCell::new(
+            BuilderDataType::Integer(1),
+        )))]),
+        BuilderDataType::List(vec![BuilderDataType::Store(Rc::new(RefCell::new(
+            BuilderDataType::Integer(1),
+        )))]),
+        BuilderDataType::List(vec![BuilderDataType::Integer(1)]),
+    ]);
+    let BuilderDataType::List(v) = data.dedup() else {
+        panic!("expected a list");
+    };
+    let kinds: Vec<_> = v.iter().map(BuilderDataType::kind).collect();
+    assert_eq!(
+        vec![
+            "Reference",
+            "List",
+            "List",
+            "List",
+            "List",
+            "List",
+            "Reference"
+        ],
+        kinds
+    );
+    let (BuilderDataType::Reference(first), BuilderDataType::Reference(last)) = (&v[0], &v[6])
+    else {
+        unreachable!();
+    };
+    assert!(Rc::ptr_eq(first, last));
+}
+
+#[test]
+fn test_duration() {
+    use std::time::Duration;
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Duration(vec![
+            BuilderDataType::Unsigned(3),
+            BuilderDataType::Unsigned(500),
+        ]),
+        BuilderDataType::Duration(vec![BuilderDataType::Number(1.25)]),
+        BuilderDataType::Duration(vec![BuilderDataType::Unsigned(60)]),
+    ]);
+    let expected = vec![
+        Duration::new(3, 500),
+        Duration::from_millis(1250),
+        Duration::from_secs(60),
+    ];
+
+    assert_eq!(Ok(expected.clone()), from_ref::<Vec<Duration>>(&data));
+    assert_eq!(Ok(expected), from_data::<Vec<Duration>>(data));
+
+    let data = BuilderDataType::Duration(vec![BuilderDataType::Number(-1.0)]);
+    assert_eq!(
+        Err(BuilderError::InvalidFunctionArgument),
+        from_ref::<Duration>(&data)
+    );
+}
+
+#[test]
+fn test_char_keys() {
+    let data = BuilderDataType::Map(vec![
+        (
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Integer(1),
+        ),
+        (
+            BuilderDataType::String(Cow::Owned(String::from("é"))),
+            BuilderDataType::Integer(2),
+        ),
+    ]);
+    let expected = BTreeMap::from([('a', 1), ('é', 2)]);
+
+    assert_eq!(Ok(expected.clone()), from_ref::<BTreeMap<char, i32>>(&data));
+    assert_eq!(Ok(expected), from_data::<BTreeMap<char, i32>>(data));
+
+    let data = BuilderDataType::Map(vec![(
+        BuilderDataType::String(Cow::from("ab")),
+        BuilderDataType::Integer(1),
+    )]);
+    assert!(from_ref::<BTreeMap<char, i32>>(&data).is_err());
+}
+
+#[test]
+fn test_shuffle() {
+    let input: Vec<u64> = (0..20).collect();
+    let data = BuilderDataType::Shuffle(Box::new(BuilderDataType::List(
+        input
+            .iter()
+            .map(|&n| BuilderDataType::Unsigned(n))
+            .collect(),
+    )));
+    let config = DeserializerConfig::new().seed(42);
+
+    let first: Vec<u64> = config.deserialize_ref(&data).unwrap();
+    let second: Vec<u64> = config.deserialize(data.clone()).unwrap();
+    assert_eq!(first, second);
+    assert_ne!(input, first);
+
+    let mut sorted = first.clone();
+    sorted.sort();
+    assert_eq!(input, sorted);
+
+    let other: Vec<u64> = DeserializerConfig::new()
+        .seed(7)
+        .deserialize_ref(&data)
+        .unwrap();
+    assert_ne!(first, other);
+
+    // A closure draws from the same sequence as its parent instead of repeating it.
+    let list = || {
+        BuilderDataType::Shuffle(Box::new(BuilderDataType::List(
+            input
+                .iter()
+                .map(|&n| BuilderDataType::Unsigned(n))
+                .collect(),
+        )))
+    };
+    let data = BuilderDataType::List(vec![BuilderDataType::Closure(vec![list()]), list()]);
+    let test: Vec<Vec<u64>> = config.deserialize_ref(&data).unwrap();
+    assert_eq!(first, test[0]);
+    assert_ne!(test[0], test[1]);
+    let test: Vec<Vec<u64>> = config.deserialize(data.clone()).unwrap();
+    assert_eq!(first, test[0]);
+    assert_ne!(test[0], test[1]);
+    let test: Vec<Vec<u64>> = from_ref(&data).unwrap();
+    assert_ne!(test[0], test[1]);
+}
+
+#[test]
+fn test_weighted() {
+    let weighted = |a: u64, b: u64| {
+        BuilderDataType::Weighted(vec![
+            BuilderDataType::Unsigned(a),
+            BuilderDataType::String(Cow::from("a")),
+            BuilderDataType::Unsigned(b),
+            BuilderDataType::String(Cow::from("b")),
+        ])
+    };
+    let data = BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(100), weighted(1, 3)]);
+    let config = DeserializerConfig::new().seed(42);
+
+    let first: Vec<String> = config.deserialize_ref(&data).unwrap();
+    let second: Vec<String> = config.deserialize(data).unwrap();
+    assert_eq!(first, second);
+    let a = first.iter().filter(|s| *s == "a").count();
+    assert!(a > 0 && a < 50);
+
+    let data = BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(20), weighted(0, 5)]);
+    let test: Vec<String> = config.deserialize_ref(&data).unwrap();
+    assert!(test.iter().all(|s| s == "b"));
+
+    assert_eq!(
+        Err(BuilderError::ZeroTotalWeight),
+        from_ref::<String>(&weighted(0, 0))
+    );
+}
+
+#[test]
+fn test_group_keys() {
+    let entry = |k: &'static str, v: i64| {
+        (
+            BuilderDataType::String(Cow::from(k)),
+            BuilderDataType::Integer(v),
+        )
+    };
+    let data = BuilderDataType::Map(vec![
+        entry("a", 1),
+        entry("a", 2),
+        entry("b", 3),
+        entry("b", 4),
+    ]);
+    let config = DeserializerConfig::new().group_keys(true);
+    let expected = HashMap::from([("a".to_owned(), vec![1, 2]), ("b".to_owned(), vec![3, 4])]);
+
+    let test: HashMap<String, Vec<i32>> = config.deserialize_ref(&data).unwrap();
+    assert_eq!(expected, test);
+    let test: HashMap<String, Vec<i32>> = config.deserialize(data).unwrap();
+    assert_eq!(expected, test);
+
+    struct Entries(Vec<(String, i64)>);
+
+    impl<'de> Deserialize<'de> for Entries {
+        fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            struct EntriesVisitor;
+
+            impl<'de> serde::de::Visitor<'de> for EntriesVisitor {
+                type Value = Entries;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a map")
+                }
+
+                fn visit_map<A>(self, mut map: A) -> Result<Entries, A::Error>
+                where
+                    A: serde::de::MapAccess<'de>,
+                {
+                    let mut entries = Vec::new();
+                    while let Some(entry) = map.next_entry()? {
+                        entries.push(entry);
+                    }
+                    Ok(Entries(entries))
+                }
+            }
+
+            deserializer.deserialize_map(EntriesVisitor)
+        }
+    }
+
+    // Only consecutive keys are grouped.
+    let data = BuilderDataType::Map(vec![entry("a", 1), entry("b", 2), entry("a", 3)]);
+    let expected = vec![
+        ("a".to_owned(), 1),
+        ("b".to_owned(), 2),
+        ("a".to_owned(), 3),
+    ];
+    let test: Entries = config.deserialize_ref(&data).unwrap();
+    assert_eq!(expected, test.0);
+    let test: Entries = config.deserialize(data).unwrap();
+    assert_eq!(expected, test.0);
+}
+
+#[test]
+fn test_named_function() {
+    let fact = BuilderDataType::IfThenElse(vec![
+        BuilderDataType::Argument(0),
+        BuilderDataType::Multiply(vec![
+            BuilderDataType::Argument(0),
+            BuilderDataType::CallFn(vec![
+                BuilderDataType::String(Cow::from("fact")),
+                BuilderDataType::Sum(vec![
+                    BuilderDataType::Argument(0),
+                    BuilderDataType::Integer(-1),
+                ]),
+            ]),
+        ]),
+        BuilderDataType::Integer(1),
+    ]);
+    let data = BuilderDataType::DefineFn(vec![
+        BuilderDataType::String(Cow::from("fact")),
+        fact,
+        BuilderDataType::Repeat(vec![
+            BuilderDataType::Integer(6),
+            BuilderDataType::CallFn(vec![
+                BuilderDataType::String(Cow::from("fact")),
+                BuilderDataType::Index,
+            ]),
+        ]),
+    ]);
+    let expected = vec![1, 1, 2, 6, 24, 120];
+    assert_eq!(Ok(&expected), from_ref::<Vec<i64>>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<i64>>(data));
+
+    let data = BuilderDataType::CallFn(vec![BuilderDataType::String(Cow::from("missing"))]);
+    assert_eq!(
+        Err(BuilderError::UndefinedFunction("missing".to_owned())),
+        from_ref::<i64>(&data)
+    );
+}
+
+#[test]
+fn test_short_list_into_struct_default() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Test {
+        a: i32,
+        b: String,
+        #[serde(default)]
+        c: Vec<i32>,
+    }
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Integer(1),
+        BuilderDataType::String(Cow::from("b")),
+    ]);
+    let expected = Test {
+        a: 1,
+        b: "b".to_owned(),
+        c: Vec::new(),
+    };
+    assert_eq!(Ok(&expected), from_ref::<Test>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Test>(data));
+
+    let data = BuilderDataType::List(vec![BuilderDataType::Integer(1)]);
+    assert!(from_ref::<Test>(&data).is_err());
+}
+
+#[test]
+fn test_tuple_map_keys() {
+    let entry = |a: i64, b: i64, v: &'static str| {
+        (
+            BuilderDataType::List(vec![
+                BuilderDataType::Integer(a),
+                BuilderDataType::Integer(b),
+            ]),
+            BuilderDataType::String(Cow::from(v)),
+        )
+    };
+    let data = BuilderDataType::Map(vec![entry(0, 1, "a"), entry(2, -3, "b")]);
+    let expected = BTreeMap::from([((0, 1), "a".to_owned()), ((2, -3), "b".to_owned())]);
+    assert_eq!(
+        Ok(&expected),
+        from_ref::<BTreeMap<(i32, i32), String>>(&data).as_ref()
+    );
+    assert_eq!(
+        Ok(expected),
+        from_data::<BTreeMap<(i32, i32), String>>(data)
+    );
+}
+
+#[test]
+fn test_skip_empty() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Integer(1),
+        BuilderDataType::Empty,
+        BuilderDataType::Index,
+        BuilderDataType::Empty,
+    ]);
+    let config = DeserializerConfig::new().skip_empty(true);
+
+    let test: Vec<i32> = config.deserialize_ref(&data).unwrap();
+    assert_eq!(vec![1, 1], test);
+    let test: Vec<i32> = config.deserialize(data.clone()).unwrap();
+    assert_eq!(vec![1, 1], test);
+
+    assert!(from_ref::<Vec<i32>>(&data).is_err());
+
+    // Fixed-length targets and size hints count only the elements that are kept.
+    let test: (i32, i32) = config.deserialize_ref(&data).unwrap();
+    assert_eq!((1, 1), test);
+    let test: (i32, i32) = config.deserialize(data.clone()).unwrap();
+    assert_eq!((1, 1), test);
+
+    struct SizeHint(Option<usize>);
+    impl<'de> Deserialize<'de> for SizeHint {
+        fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            struct V;
+            impl<'de> serde::de::Visitor<'de> for V {
+                type Value = SizeHint;
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a sequence")
+                }
+                fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                    self,
+                    mut seq: A,
+                ) -> Result<SizeHint, A::Error> {
+                    let hint = seq.size_hint();
+                    while seq.next_element::<serde::de::IgnoredAny>()?.is_some() {}
+                    Ok(SizeHint(hint))
+                }
+            }
+            d.deserialize_seq(V)
+        }
+    }
+    let test: SizeHint = config.deserialize_ref(&data).unwrap();
+    assert_eq!(Some(2), test.0);
+    let test: SizeHint = config.deserialize(data).unwrap();
+    assert_eq!(Some(2), test.0);
+}
+
+#[test]
+fn test_sum_takes_from_argument() {
+    let data = BuilderDataType::Apply(
+        Box::new(BuilderDataType::Sum(vec![
+            BuilderDataType::TakeFromArgument(0),
+            BuilderDataType::TakeFromArgument(0),
+            BuilderDataType::IfThenElse(vec![
+                BuilderDataType::Argument(1),
+                BuilderDataType::Integer(10),
+                BuilderDataType::Integer(20),
+            ]),
+        ])),
+        vec![
+            BuilderDataType::List(vec![
+                BuilderDataType::Integer(1),
+                BuilderDataType::Integer(2),
+            ]),
+            BuilderDataType::Boolean(true),
+        ],
+    );
+    assert_eq!(Ok(13), from_ref::<i64>(&data));
+    assert_eq!(Ok(13), from_data::<i64>(data));
+}
+
+#[test]
+fn test_pure_repeat_resolves_once() {
+    let repeat = |extra: BuilderDataType<'static>| {
+        BuilderDataType::Repeat(vec![
+            BuilderDataType::Unsigned(1000),
+            BuilderDataType::Sum(vec![
+                BuilderDataType::Integer(1),
+                BuilderDataType::Integer(2),
+                extra,
+            ]),
+        ])
+    };
+    let pure = repeat(BuilderDataType::Integer(0));
+    let indexed = repeat(BuilderDataType::Multiply(vec![
+        BuilderDataType::Index,
+        BuilderDataType::Integer(0),
+    ]));
+
+    let expected = vec![3; 1000];
+    assert_eq!(Ok(&expected), from_ref::<Vec<i64>>(&pure).as_ref());
+    let (test, stats): (Vec<i64>, Stats) = from_data_with_stats(pure).unwrap();
+    assert_eq!(expected, test);
+    assert_eq!(1, stats.clones);
+
+    let (test, stats): (Vec<i64>, Stats) = from_data_with_stats(indexed).unwrap();
+    assert_eq!(expected, test);
+    assert_eq!(1000, stats.clones);
+}
+
+#[test]
+fn test_error_context() {
+    let data = BuilderDataType::Sum(vec![
+        BuilderDataType::Integer(1),
+        BuilderDataType::Integer(2),
+        BuilderDataType::Multiply(vec![
+            BuilderDataType::ParseInt(vec![BuilderDataType::String(Cow::from("x"))]),
+            BuilderDataType::Integer(3),
+        ]),
+    ]);
+    let expected = BuilderError::Context {
+        op: "Sum",
+        index: 2,
+        source: Box::new(BuilderError::Context {
+            op: "Multiply",
+            index: 0,
+            source: Box::new(BuilderError::ParseFailed("x".to_owned())),
+        }),
+    };
+    assert_eq!(Err(&expected), from_ref::<i64>(&data).as_ref());
+    assert_eq!(
+        "while evaluating Sum operand 2: while evaluating Multiply operand 0: \
+         Failed to parse number: x",
+        from_data::<i64>(data).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn test_list_spread() {
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Integer(1),
+        BuilderDataType::Spread(Box::new(BuilderDataType::List(vec![
+            BuilderDataType::Integer(2),
+            BuilderDataType::Spread(Box::new(BuilderDataType::IfThenElse(vec![
+                BuilderDataType::Boolean(true),
+                BuilderDataType::List(vec![
+                    BuilderDataType::Integer(3),
+                    BuilderDataType::Integer(3),
+                ]),
+                BuilderDataType::Empty,
+            ]))),
+        ]))),
+        BuilderDataType::Spread(Box::new(BuilderDataType::Empty)),
+        BuilderDataType::Integer(4),
+    ]);
+    let expected = vec![1, 2, 3, 3, 4];
+    assert_eq!(Ok(&expected), from_ref::<Vec<i64>>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<i64>>(data));
+
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Index,
+        BuilderDataType::Spread(Box::new(BuilderDataType::List(vec![
+            BuilderDataType::Index,
+            BuilderDataType::Index,
+        ]))),
+        BuilderDataType::Index,
+    ]);
+    let expected = vec![0, 1, 2, 3];
+    assert_eq!(Ok(&expected), from_ref::<Vec<u64>>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<u64>>(data));
+
+    // Fixed-length targets count the spread elements, not the operands.
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Integer(1),
+        BuilderDataType::Spread(Box::new(BuilderDataType::List(vec![
+            BuilderDataType::Integer(2),
+            BuilderDataType::Integer(3),
+        ]))),
+    ]);
+    assert_eq!(Ok([1, 2, 3]), from_ref::<[i32; 3]>(&data));
+    assert_eq!(Ok((1, 2, 3)), from_ref::<(i32, i32, i32)>(&data));
+    assert_eq!(Ok([1, 2, 3]), from_data::<[i32; 3]>(data.clone()));
+    assert_eq!(Ok((1, 2, 3)), from_data::<(i32, i32, i32)>(data));
+}
+
+#[test]
+fn test_numeric_string_map_keys() {
+    let data = BuilderDataType::Map(vec![(
+        BuilderDataType::String(Cow::from("5")),
+        BuilderDataType::String(Cow::from("x")),
+    )]);
+    let expected = BTreeMap::from([(5, "x".to_owned())]);
+    assert_eq!(
+        Ok(&expected),
+        from_ref::<BTreeMap<u32, String>>(&data).as_ref()
+    );
+    assert_eq!(
+        Ok(&expected),
+        from_data::<BTreeMap<u32, String>>(data.clone()).as_ref()
+    );
+
+    let strict = DeserializerConfig::new().strict(true);
+    assert!(strict
+        .deserialize_ref::<BTreeMap<u32, String>>(&data)
+        .is_err());
+}
+
+#[test]
+fn test_check_arity() {
+    let data = BuilderDataType::Closure(vec![
+        BuilderDataType::List(vec![
+            BuilderDataType::Argument(1),
+            BuilderDataType::Apply(
+                Box::new(BuilderDataType::Argument(0)),
+                vec![BuilderDataType::Argument(5)],
+            ),
+        ]),
+        BuilderDataType::Integer(1),
+        BuilderDataType::Integer(2),
+    ]);
+    let config = DeserializerConfig::new().check_arity(true);
+    let expected = BuilderError::ArgCountMismatch {
+        needed: 6,
+        provided: 3,
+    };
+    assert_eq!(
+        Err(&expected),
+        config.deserialize_ref::<Vec<i64>>(&data).as_ref()
+    );
+    assert_eq!(
+        "Closure body needs 6 arguments, 3 provided.",
+        config
+            .deserialize::<Vec<i64>>(data.clone())
+            .unwrap_err()
+            .to_string()
+    );
+    assert_eq!(
+        Err(BuilderError::InvalidFunctionArgument),
+        from_ref::<Vec<i64>>(&data)
+    );
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_format_date() {
+    let format_date = |secs: i64, format: &'static str| {
+        BuilderDataType::FormatDate(vec![
+            BuilderDataType::Integer(secs),
+            BuilderDataType::String(Cow::from(format)),
+        ])
+    };
+    let data = format_date(1_609_459_200, "%Y-%m-%d");
+    assert_eq!(Ok("2021-01-01".to_owned()), from_ref::<String>(&data));
+    assert_eq!(Ok("2021-01-01".to_owned()), from_data::<String>(data));
+
+    assert_eq!(
+        Err(BuilderError::InvalidDateFormat("%Y-%Q".to_owned())),
+        from_ref::<String>(&format_date(0, "%Y-%Q"))
+    );
+    assert_eq!(
+        Err(BuilderError::InvalidFunctionArgument),
+        from_ref::<String>(&format_date(i64::MAX, "%Y"))
+    );
+}
+
+#[test]
+fn test_borrowed_map_keys() {
+    let input = String::from("key");
+    let data = BuilderDataType::Map(vec![(
+        BuilderDataType::String(Cow::Borrowed(&input[..])),
+        BuilderDataType::Integer(1),
+    )]);
+    let test: HashMap<&str, i32> = from_ref(&data).unwrap();
+    let (key, value) = test.iter().next().unwrap();
+    assert_eq!(1, *value);
+    assert_eq!(input.as_ptr(), key.as_ptr());
+
+    let test: HashMap<&str, i32> = DeserializerConfig::new()
+        .duplicate_keys(DuplicateKeyPolicy::Error)
+        .deserialize_ref(&data)
+        .unwrap();
+    assert_eq!(input.as_ptr(), test.keys().next().unwrap().as_ptr());
+}
+
+#[test]
+fn test_repeat_map_index_keys() {
+    let data = BuilderDataType::RepeatMap(vec![
+        BuilderDataType::Unsigned(3),
+        BuilderDataType::Index,
+        BuilderDataType::String(Cow::from("a")),
+    ]);
+    let expected = HashMap::from([
+        (0, "a".to_owned()),
+        (1, "a".to_owned()),
+        (2, "a".to_owned()),
+    ]);
+    assert_eq!(
+        Ok(&expected),
+        from_ref::<HashMap<i64, String>>(&data).as_ref()
+    );
+    assert_eq!(Ok(expected), from_data::<HashMap<i64, String>>(data));
+}
+
+#[test]
+fn test_partition() {
+    // `Argument(0) - Argument(0) / 2 * 2` is the remainder of the element divided by two.
+    let remainder = BuilderDataType::Sum(vec![
+        BuilderDataType::Argument(0),
+        BuilderDataType::Multiply(vec![
+            BuilderDataType::Divide(vec![
+                BuilderDataType::Argument(0),
+                BuilderDataType::Integer(2),
+            ]),
+            BuilderDataType::Integer(-2),
+        ]),
+    ]);
+    let data = BuilderDataType::Partition(vec![
+        BuilderDataType::List((1..=6).map(BuilderDataType::Integer).collect()),
+        BuilderDataType::Switch(vec![
+            remainder,
+            BuilderDataType::Integer(0),
+            BuilderDataType::Boolean(true),
+            BuilderDataType::Boolean(false),
+        ]),
+    ]);
+    let expected = (vec![2, 4, 6], vec![1, 3, 5]);
+    assert_eq!(
+        Ok(&expected),
+        from_ref::<(Vec<i64>, Vec<i64>)>(&data).as_ref()
+    );
+    assert_eq!(Ok(expected), from_data::<(Vec<i64>, Vec<i64>)>(data));
+}
+
+#[test]
+fn test_store_same_through_both_entry_points() {
+    let graph = || {
+        let counter = Rc::new(RefCell::new(BuilderDataType::List(vec![
+            BuilderDataType::Integer(3),
+            BuilderDataType::Integer(2),
+            BuilderDataType::Integer(1),
+        ])));
+        let store = Rc::new(RefCell::new(BuilderDataType::List(vec![
+            BuilderDataType::Take(counter.clone()),
+            BuilderDataType::Integer(10),
+        ])));
+        BuilderDataType::List(vec![
+            BuilderDataType::Store(store.clone()),
+            BuilderDataType::Store(store),
+            BuilderDataType::List(vec![BuilderDataType::Take(counter)]),
+        ])
+    };
+    let expected = vec![vec![1, 10], vec![2, 10], vec![3]];
+    assert_eq!(Ok(&expected), from_ref::<Vec<Vec<i64>>>(&graph()).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<Vec<i64>>>(graph()));
+}
+
+#[test]
+fn test_take_counter_exhausts_to_empty() {
+    let counter = Rc::new(RefCell::new(BuilderDataType::Integer(2)));
+    let data = BuilderDataType::Repeat(vec![
+        BuilderDataType::Unsigned(4),
+        BuilderDataType::Take(counter.clone()),
+    ]);
+    assert_eq!(
+        Ok(vec![Some(2), Some(1), None, None]),
+        from_ref::<Vec<Option<i64>>>(&data)
+    );
+
+    *counter.borrow_mut() = BuilderDataType::Unsigned(2);
+    assert_eq!(
+        Ok(vec![Some(2), Some(1), None, None]),
+        from_data::<Vec<Option<u64>>>(data)
+    );
+}
+
+#[test]
+fn test_into_json_value() {
+    let entry =
+        |k: &'static str, v: BuilderDataType<'static>| (BuilderDataType::String(Cow::from(k)), v);
+    let data = BuilderDataType::Map(vec![
+        entry(
+            "repeat",
+            BuilderDataType::Repeat(vec![BuilderDataType::Unsigned(3), BuilderDataType::Index]),
+        ),
+        entry(
+            "sum",
+            BuilderDataType::Sum(vec![
+                BuilderDataType::Integer(1),
+                BuilderDataType::Number(0.5),
+            ]),
+        ),
+        entry(
+            "nested",
+            BuilderDataType::Map(vec![
+                entry("empty", BuilderDataType::Empty),
+                entry("null", BuilderDataType::Null),
+                entry("flag", BuilderDataType::Boolean(true)),
+            ]),
+        ),
+    ]);
+    let expected = serde_json::json!({
+        "repeat": [0, 1, 2],
+        "sum": 1.5,
+        "nested": {"empty": null, "null": null, "flag": true},
+    });
+    assert_eq!(Ok(&expected), from_ref::<serde_json::Value>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<serde_json::Value>(data));
+}
+
+#[test]
+fn test_toggle() {
+    let flag = Rc::new(RefCell::new(true));
+    let data = BuilderDataType::Repeat(vec![
+        BuilderDataType::Unsigned(4),
+        BuilderDataType::Toggle(flag.clone()),
+    ]);
+    let expected = vec![true, false, true, false];
+    assert_eq!(Ok(&expected), from_ref::<Vec<bool>>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<Vec<bool>>(data));
+
+    let data = BuilderDataType::Repeat(vec![
+        BuilderDataType::Unsigned(4),
+        BuilderDataType::IfThenElse(vec![
+            BuilderDataType::Toggle(flag),
+            BuilderDataType::String(Cow::from("on")),
+            BuilderDataType::String(Cow::from("off")),
+        ]),
+    ]);
+    assert_eq!(
+        Ok(vec!["on", "off", "on", "off"]),
+        from_ref::<Vec<&str>>(&data)
+    );
+
+    // A closure body is not evaluated as its own argument, so it flips once per read.
+    let flag = Rc::new(RefCell::new(true));
+    let data = BuilderDataType::List(vec![
+        BuilderDataType::Closure(vec![BuilderDataType::Toggle(flag.clone())]),
+        BuilderDataType::Closure(vec![BuilderDataType::Toggle(flag.clone())]),
+    ]);
+    assert_eq!(Ok(vec![true, false]), from_ref::<Vec<bool>>(&data));
+    assert_eq!(Ok(vec![true, false]), from_data::<Vec<bool>>(data));
+
+    // A template puts the flag back before each run.
+    let template = Template::new(BuilderDataType::List(vec![BuilderDataType::Toggle(
+        Rc::new(RefCell::new(false)),
+    )]));
+    assert_eq!(Ok(vec![false]), template.deserialize::<Vec<bool>>());
+    assert_eq!(Ok(vec![false]), template.deserialize::<Vec<bool>>());
+}
+
+#[test]
+fn test_deserialize_datatype() {
+    let json =
+        r#"{"name":"x","tags":["a","b"],"size":3,"offset":-2,"ratio":0.5,"on":true,"none":null}"#;
+    let data: BuilderDataType = serde_json::from_str(json).unwrap();
+    assert!(matches!(
+        &data,
+        BuilderDataType::Map(v) if matches!(v[0].1, BuilderDataType::String(Cow::Borrowed("x")))
+    ));
+
+    let expected: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(Ok(&expected), from_ref::<serde_json::Value>(&data).as_ref());
+    assert_eq!(Ok(expected), from_data::<serde_json::Value>(data));
+}
@@ -0,0 +1,49 @@
+use crate::closure::SIZE_HINT_LIMIT;
+use crate::{BuilderDataType, BuilderDeserializerRef, BuilderError, Closure};
+use serde::de::{DeserializeSeed, SeqAccess};
+
+pub struct BuilderUnfoldAccess<'s, 'r, 'de>
+where
+    'de: 'r,
+{
+    pub(crate) closure: &'s mut Closure<'de>,
+    pub(crate) current: BuilderDataType<'de>,
+    pub(crate) step: &'r BuilderDataType<'de>,
+    pub(crate) count: usize,
+    pub(crate) index: usize,
+}
+
+impl<'s, 'r, 'de> SeqAccess<'de> for BuilderUnfoldAccess<'s, 'r, 'de> {
+    type Error = BuilderError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.index >= self.count {
+            return Ok(None);
+        }
+        let outer = self.closure.index;
+        self.closure.index = self.index;
+        self.index += 1;
+        let value = seed.deserialize(BuilderDeserializerRef {
+            closure: self.closure,
+            data: &self.current,
+        });
+        self.closure.index = outer;
+        let value = value?;
+        // The step is only evaluated for elements that will be emitted.
+        if self.index < self.count {
+            let current = core::mem::replace(&mut self.current, BuilderDataType::Empty);
+            self.current = self
+                .closure
+                .nested(vec![current])
+                .evaluate(self.step.clone())?;
+        }
+        Ok(Some(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.count.saturating_sub(self.index).min(SIZE_HINT_LIMIT))
+    }
+}
@@ -0,0 +1,250 @@
+use crate::closure::{operand_count, operands, repeat_operands};
+use crate::{BuilderDataType, BuilderError};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+struct Validator<'de> {
+    errors: Vec<BuilderError>,
+    /// Nodes between the current one and the nearest enclosing conditional.
+    path: Vec<*const BuilderDataType<'de>>,
+    /// `SelfReference` targets already checked, so cyclic graphs terminate.
+    followed: Vec<*const BuilderDataType<'de>>,
+}
+
+impl<'de> Validator<'de> {
+    fn check(&mut self, node: &BuilderDataType<'de>, args: usize) {
+        self.path.push(node);
+        if let Some((v, min, max)) = operand_bounds(node) {
+            if let Err(e) = operand_count(node.kind(), v.len(), min, max) {
+                self.errors.push(e);
+            }
+        }
+        match node {
+            BuilderDataType::Argument(n) | BuilderDataType::TakeFromArgument(n) => {
+                if *n >= args {
+                    self.errors.push(BuilderError::InvalidFunctionArgument);
+                }
+            }
+            BuilderDataType::Closure(v) => {
+                if v.is_empty() {
+                    self.errors.push(BuilderError::EmptyClosure);
+                }
+                for (i, e) in v.iter().enumerate() {
+                    self.check_argument(e, i > 0, args, v.len());
+                }
+            }
+            BuilderDataType::Apply(body, v) => {
+                self.check(body, v.len());
+                for e in v {
+                    self.check_argument(e, true, args, v.len());
+                }
+            }
+            BuilderDataType::IfThenElse(v) => {
+                if let Err(e) = operands::<3>("IfThenElse", v) {
+                    self.errors.push(e);
+                }
+                self.check_branches(v, args);
+            }
+            BuilderDataType::Switch(v) | BuilderDataType::While(v) | BuilderDataType::Try(v) => {
+                self.check_branches(v, args)
+            }
+            BuilderDataType::RepeatMap(v) => {
+                if let Err(e) = operands::<3>("RepeatMap", v) {
+                    self.errors.push(e);
+                }
+                v.iter().for_each(|e| self.check(e, args));
+            }
+            BuilderDataType::Unfold(v) => {
+                if let Err(e) = operands::<3>("Unfold", v) {
+                    self.errors.push(e);
+                }
+                // The step runs with the previous value as its only argument.
+                for (i, e) in v.iter().enumerate() {
+                    self.check(e, if i == 2 { 1 } else { args });
+                }
+            }
+            #[cfg(feature = "regex")]
+            BuilderDataType::Matches(v) | BuilderDataType::Capture(v) => {
+                if let Err(e) = operands::<2>(node.kind(), v) {
+                    self.errors.push(e);
+                }
+                v.iter().for_each(|e| self.check(e, args));
+            }
+            #[cfg(feature = "chrono")]
+            BuilderDataType::FormatDate(v) => {
+                if let Err(e) = operands::<2>("FormatDate", v) {
+                    self.errors.push(e);
+                }
+                v.iter().for_each(|e| self.check(e, args));
+            }
+            BuilderDataType::Quote(v) | BuilderDataType::Variant(v) => {
+                if let Err(e) = operands::<2>(node.kind(), v) {
+                    self.errors.push(e);
+                }
+                v.iter().for_each(|e| self.check(e, args));
+            }
+            BuilderDataType::DefineFn(v) => {
+                if let Err(e) = operands::<3>("DefineFn", v) {
+                    self.errors.push(e);
+                }
+                // The function runs with the arguments of each call, which are not known here.
+                for (i, e) in v.iter().enumerate() {
+                    self.check(e, if i == 1 { usize::MAX } else { args });
+                }
+            }
+            BuilderDataType::Partition(v) => {
+                if let Err(e) = operands::<2>("Partition", v) {
+                    self.errors.push(e);
+                }
+                // The predicate runs with the element as its only argument.
+                for (i, e) in v.iter().enumerate() {
+                    self.check(e, if i == 1 { 1 } else { args });
+                }
+            }
+            BuilderDataType::Scan(v) => {
+                // The combiner runs with the accumulator and the element as its arguments.
+                for (i, e) in v.iter().enumerate() {
+                    self.check(e, if i == 2 { 2 } else { args });
+                }
+            }
+            BuilderDataType::Replace(v) => {
+                if let Err(e) = operands::<3>("Replace", v) {
+                    self.errors.push(e);
+                }
+                v.iter().for_each(|e| self.check(e, args));
+            }
+            BuilderDataType::Repeat(v) => {
+                if let Err(e) = repeat_operands(v) {
+                    self.errors.push(e);
+                }
+                v.iter().for_each(|e| self.check(e, args));
+            }
+            BuilderDataType::SelfReference(w) => match w.upgrade() {
+                None => self.errors.push(BuilderError::InvalidSelfRefrence),
+                Some(target) => self.check_target(target, args),
+            },
+            BuilderDataType::SelfReferenceOr(w, default) => match w.upgrade() {
+                None => self.check(default, args),
+                Some(target) => self.check_target(target, args),
+            },
+            _ => node.for_each_child(&mut |child| self.check(child, args)),
+        }
+        self.path.pop();
+    }
+
+    fn check_target(&mut self, target: Rc<BuilderDataType<'de>>, args: usize) {
+        let target_ptr = Rc::as_ptr(&target);
+        if self.path.contains(&target_ptr) {
+            self.errors.push(BuilderError::UnguardedCycle);
+        } else if !self.followed.contains(&target_ptr) {
+            self.followed.push(target_ptr);
+            self.check(&target, args);
+        }
+    }
+
+    /// Arguments are resolved in the caller's scope but their contents run in the callee's.
+    fn check_argument(
+        &mut self,
+        e: &BuilderDataType<'de>,
+        resolved: bool,
+        outer: usize,
+        inner: usize,
+    ) {
+        match e {
+            BuilderDataType::Argument(_) | BuilderDataType::TakeFromArgument(_) if resolved => {
+                self.check(e, outer)
+            }
+            _ => self.check(e, inner),
+        }
+    }
+
+    /// Checks the first operand in place and the rest as conditionally evaluated.
+    fn check_branches(&mut self, v: &[BuilderDataType<'de>], args: usize) {
+        let Some((first, rest)) = v.split_first() else {
+            return;
+        };
+        self.check(first, args);
+        let path = core::mem::take(&mut self.path);
+        rest.iter().for_each(|e| self.check(e, args));
+        self.path = path;
+    }
+}
+
+/// Operands and their allowed count for the operations not checked in `Validator::check`.
+fn operand_bounds<'a, 'de>(
+    node: &'a BuilderDataType<'de>,
+) -> Option<(&'a [BuilderDataType<'de>], usize, usize)> {
+    Some(match node {
+        BuilderDataType::While(v)
+        | BuilderDataType::Try(v)
+        | BuilderDataType::Tap(v)
+        | BuilderDataType::Range(v) => (v, 2, 2),
+        BuilderDataType::Scan(v) | BuilderDataType::Pad(v) | BuilderDataType::Let(v) => (v, 3, 3),
+        BuilderDataType::Assert(v) => (v, 2, 3),
+        BuilderDataType::ParseInt(v) | BuilderDataType::Duration(v) => (v, 1, 2),
+        BuilderDataType::ParseFloat(v) => (v, 1, 1),
+        BuilderDataType::Path(v)
+        | BuilderDataType::Sum(v)
+        | BuilderDataType::Multiply(v)
+        | BuilderDataType::Divide(v) => (v, 1, usize::MAX),
+        BuilderDataType::Switch(v) | BuilderDataType::Weighted(v) => (v, 2, usize::MAX),
+        _ => return None,
+    })
+}
+
+impl<'de> BuilderDataType<'de> {
+    /// Number of arguments this node reads from the enclosing scope: one past the highest
+    /// `Argument` or `TakeFromArgument` index. Bodies that run with arguments of their own only
+    /// count the operands resolved in place.
+    pub(crate) fn arguments_needed(&self) -> usize {
+        let in_place = |e: &BuilderDataType<'de>| match e {
+            BuilderDataType::Argument(n) | BuilderDataType::TakeFromArgument(n) => n + 1,
+            _ => 0,
+        };
+        let mut needed = 0;
+        match self {
+            BuilderDataType::Argument(n) | BuilderDataType::TakeFromArgument(n) => needed = n + 1,
+            BuilderDataType::Closure(v) => {
+                needed = v.iter().skip(1).map(in_place).max().unwrap_or(0)
+            }
+            BuilderDataType::Apply(_, v) | BuilderDataType::CallFn(v) => {
+                needed = v.iter().map(in_place).max().unwrap_or(0)
+            }
+            BuilderDataType::Unfold(v) | BuilderDataType::Scan(v) => {
+                needed = v
+                    .iter()
+                    .take(2)
+                    .map(Self::arguments_needed)
+                    .max()
+                    .unwrap_or(0)
+            }
+            BuilderDataType::Partition(v) => needed = v.first().map_or(0, Self::arguments_needed),
+            BuilderDataType::DefineFn(v) => {
+                for (i, e) in v.iter().enumerate() {
+                    if i != 1 {
+                        needed = needed.max(e.arguments_needed());
+                    }
+                }
+            }
+            _ => self.for_each_child(&mut |child| needed = needed.max(child.arguments_needed())),
+        }
+        needed
+    }
+
+    /// Checks the graph without deserializing it, reporting every problem found: operand counts,
+    /// `Argument` indices outside their closure, dangling `SelfReference`s and self references
+    /// that recurse without passing through a conditional.
+    pub fn validate(&self) -> Result<(), Vec<BuilderError>> {
+        let mut validator = Validator {
+            errors: Vec::new(),
+            path: Vec::new(),
+            followed: Vec::new(),
+        };
+        validator.check(self, 0);
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(validator.errors)
+        }
+    }
+}
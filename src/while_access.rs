@@ -0,0 +1,37 @@
+use crate::{BuilderDataType, BuilderDeserializerRef, BuilderError, Closure};
+use serde::de::{DeserializeSeed, SeqAccess};
+
+pub struct BuilderWhileAccess<'s, 'r, 'de>
+where
+    'de: 'r,
+{
+    pub(crate) closure: &'s mut Closure<'de>,
+    pub(crate) condition: &'r BuilderDataType<'de>,
+    pub(crate) body: &'r BuilderDataType<'de>,
+    pub(crate) index: usize,
+}
+
+impl<'s, 'r, 'de> SeqAccess<'de> for BuilderWhileAccess<'s, 'r, 'de> {
+    type Error = BuilderError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if !self.closure.resolve_to_bool(self.condition)? {
+            return Ok(None);
+        }
+        if self.index >= self.closure.iteration_limit {
+            return Err(BuilderError::IterationLimit(self.closure.iteration_limit));
+        }
+        let outer = self.closure.index;
+        self.closure.index = self.index;
+        self.index += 1;
+        let value = seed.deserialize(BuilderDeserializerRef {
+            closure: self.closure,
+            data: self.body,
+        });
+        self.closure.index = outer;
+        Ok(Some(value?))
+    }
+}